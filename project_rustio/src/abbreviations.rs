@@ -5,6 +5,8 @@ use std::collections::{HashMap, HashSet};
 
 use regex::Regex;
 
+use crate::userquestions::getuserchoice;
+
 
 /////////////////////////////////
 // Abbreviation Implementation //
@@ -64,9 +66,14 @@ impl<'a> Abbreviation<'a> {
 // AbbreviationResolver Implementation //
 /////////////////////////////////////////
 
+// lookuptable is keyed by an owned String (rather than &'a str) precisely so
+// that call()/call_fallible()/call_noninteractive() can be given a
+// short-lived abbreviation string (e.g. one borrowed out of a loop-local
+// buffer) instead of requiring every caller's input to outlive 'a -- only
+// the resolved *values* need to borrow from stringcollection for 'a
 pub struct AbbreviationResolver<'a> {
 	stringcollection: &'a HashSet<&'a str>,
-	lookuptable: HashMap<&'a str, &'a str>,
+	lookuptable: HashMap<String, &'a str>,
 }
 
 impl<'a> AbbreviationResolver<'a> {
@@ -75,20 +82,57 @@ impl<'a> AbbreviationResolver<'a> {
 		Self { stringcollection, lookuptable }
 	}
 
-    fn resolveabbreviation(&'a self, abbrev: &Abbreviation) -> &'a str {
+	fn findmatches(&self, abbrev: &Abbreviation) -> Vec<&'a str> {
 		let mut possiblematches = Vec::new();
 		for &longstring in self.stringcollection {
 			if abbrev.abbreviates(longstring) {
 				possiblematches.push(longstring);
 			}
 		}
-	
+		possiblematches
+	}
+
+	// Multiple matches get routed to the user through a menu instead of
+	// panicking, since recipe entry is an interactive session anyway.
+	// Zero matches, and a cancelled/undecided menu, both resolve to None,
+	// leaving it up to the caller whether that's a hard error or not
+	fn resolveabbreviation_fallible(&self, abbrev: &Abbreviation) -> Option<&'a str> {
+		let possiblematches = self.findmatches(abbrev);
+
+		match possiblematches.len() {
+			0 => None,
+			1 => possiblematches.into_iter().next(),
+			_ => {
+				let message = format!(
+					"abbreviation {:?} is ambiguous, please pick one:",
+					abbrev.st()
+				);
+				getuserchoice(&message, possiblematches.into_iter(), "(cancel)", true)
+			},
+		}
+	}
+
+	// Used by the interactive call() path: zero matches (or no choice
+	// made in an ambiguous menu) is still a hard error for callers that
+	// have no way to report failure
+	fn resolveabbreviation(&self, abbrev: &Abbreviation) -> &'a str {
+		self.resolveabbreviation_fallible(abbrev).unwrap_or_else(|| {
+			panic!("found no strings matching abbreviation: {:?}", abbrev.st())
+		})
+	}
+
+	// A strict, non-interactive fallback that always hard-errors on an
+	// ambiguous match instead of prompting -- for batch/database loading
+	// paths where there is no user around (and no sense) to ask a question
+	fn resolveabbreviation_strict(&self, abbrev: &Abbreviation) -> &'a str {
+		let possiblematches = self.findmatches(abbrev);
+
 		match possiblematches.len() {
 			0 => {
 				panic!("found no strings matching abbreviation: {:?}", abbrev.st());
 			},
 			1 => {
-				possiblematches.first().unwrap()
+				possiblematches.into_iter().next().unwrap()
 			},
 			_ => {
 				panic!(
@@ -100,12 +144,46 @@ impl<'a> AbbreviationResolver<'a> {
 		}
 	}
 
-	pub fn call(&'a mut self, abbrev_str: &str) -> &'a str {
-		self.lookuptable
-		    .entry(abbrev_str)
-			.or_insert_with_key(|&key| {
-				let abbrev = Abbreviation::new(abbrev_str);
-				self.resolveabbreviation(&abbrev)
-			})
+	pub fn call(&mut self, abbrev_str: &str) -> &'a str {
+		if let Some(&resolved) = self.lookuptable.get(abbrev_str) {
+			return resolved;
+		}
+
+		let abbrev = Abbreviation::new(abbrev_str);
+		let resolved = self.resolveabbreviation(&abbrev);
+		self.lookuptable.insert(abbrev_str.to_owned(), resolved);
+		resolved
+	}
+
+	// A fallible counterpart of call(): resolves and caches the same way,
+	// but hands an unresolvable abbreviation back to the caller as None
+	// instead of panicking, for callers (like readrecipefile()) that need
+	// to report the failure as a structured, line-numbered parse error
+	pub fn call_fallible(&mut self, abbrev_str: &str) -> Option<&'a str> {
+		if let Some(&resolved) = self.lookuptable.get(abbrev_str) {
+			return Some(resolved);
+		}
+
+		// An empty or non-alphabetic string can never be an abbreviation
+		// (Abbreviation::new() would panic on it), so it is unresolvable too
+		if abbrev_str.is_empty() || !abbrev_str.chars().all(char::is_alphabetic) {
+			return None;
+		}
+
+		let abbrev = Abbreviation::new(abbrev_str);
+		let resolved = self.resolveabbreviation_fallible(&abbrev)?;
+		self.lookuptable.insert(abbrev_str.to_owned(), resolved);
+		Some(resolved)
+	}
+
+	pub fn call_noninteractive(&mut self, abbrev_str: &str) -> &'a str {
+		if let Some(&resolved) = self.lookuptable.get(abbrev_str) {
+			return resolved;
+		}
+
+		let abbrev = Abbreviation::new(abbrev_str);
+		let resolved = self.resolveabbreviation_strict(&abbrev);
+		self.lookuptable.insert(abbrev_str.to_owned(), resolved);
+		resolved
 	}
 }
@@ -0,0 +1,134 @@
+// algorithms.rs
+// A small relational search engine for exploring alternate recipe and
+// facility choices, in the spirit of a miniature logic/Kanren interpreter
+
+use std::rc::Rc;
+
+use num::Zero;
+
+use crate::databases::DataBase;
+use crate::factories::{Factory, RecipeCrafter};
+use crate::products::{Product, ProductQuantity};
+use crate::rates::Rate;
+
+
+/////////////////////////////////////
+// Goals and the States They Search //
+/////////////////////////////////////
+
+// A goal takes a Factory (the search state so far) and produces a lazy
+// stream of successor Factory states -- one per way the goal can be
+// satisfied. A goal that can't make progress from a given state is free to
+// yield nothing at all, pruning that branch of the search
+pub type PartialFactory<'a> = Factory<'a>;
+pub type GoalFn<'a> = Rc<dyn Fn(PartialFactory<'a>) -> Box<dyn Iterator<Item = PartialFactory<'a>> + 'a> + 'a>;
+
+// Runs two goals in sequence: every successor of the first goal is fed
+// through the second. Because goals are lazy iterators, g2 can start
+// consuming g1's first successor before g1 has finished producing the rest
+pub fn and<'a>(g1: GoalFn<'a>, g2: GoalFn<'a>) -> GoalFn<'a> {
+	Rc::new(move |state: PartialFactory<'a>| -> Box<dyn Iterator<Item = PartialFactory<'a>> + 'a> {
+		let g2 = Rc::clone(&g2);
+		Box::new(g1(state).flat_map(move |next| g2(next)))
+	})
+}
+
+// Runs two goals as alternatives, fairly interleaving their two streams of
+// successors so that an infinite branch from g1 (e.g. an unbounded recipe
+// loop) can never starve g2 of a turn, and vice versa
+pub fn or<'a>(g1: GoalFn<'a>, g2: GoalFn<'a>) -> GoalFn<'a> {
+	Rc::new(move |state: PartialFactory<'a>| -> Box<dyn Iterator<Item = PartialFactory<'a>> + 'a> {
+		Box::new(Interleave {
+			left: g1(state.clone()),
+			right: g2(state),
+			takeleft: true,
+		})
+	})
+}
+
+struct Interleave<'a> {
+	left: Box<dyn Iterator<Item = PartialFactory<'a>> + 'a>,
+	right: Box<dyn Iterator<Item = PartialFactory<'a>> + 'a>,
+	takeleft: bool,
+}
+
+impl<'a> Iterator for Interleave<'a> {
+	type Item = PartialFactory<'a>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.takeleft = !self.takeleft;
+		let (first, second) = if self.takeleft {
+			(&mut self.left, &mut self.right)
+		} else {
+			(&mut self.right, &mut self.left)
+		};
+
+		match first.next() {
+			Some(state) => Some(state),
+			None => second.next(),
+		}
+	}
+}
+
+
+///////////////////////////////////////////
+// The Expansion Goal -- One Choice Point //
+///////////////////////////////////////////
+
+// Expands one unsatisfied (non-ignored) product into every candidate
+// recipe x facility combination, each sized via with_goal() to produce
+// exactly the missing rate. A product with no known recipe is auto-ignored
+// the same way Factory::solve() handles raw ores, rather than dead-ending
+// Takes product by value (rather than &'a Product) so a caller holding only
+// a short-lived borrow of it (e.g. one read out of a Factory's rate table)
+// can still hand it off to the closure this returns without extending that
+// borrow's lifetime to 'a
+fn expandproduct<'a>(db: &'a DataBase<'a>, product: Product) -> GoalFn<'a> {
+	Rc::new(move |mut state: PartialFactory<'a>| -> Box<dyn Iterator<Item = PartialFactory<'a>> + 'a> {
+		let recipes = db.recipes_for_output(&product);
+		if recipes.is_empty() {
+			state.setignored(&product);
+			return Box::new(std::iter::once(state));
+		}
+
+		let missingrate = state.rates().get(&product).copied().unwrap_or(Rate::zero());
+		let missing = ProductQuantity::new(-missingrate, product.clone());
+
+		let successors = recipes.into_iter().flat_map(move |recipe| {
+			let facilities = db.facilities_for_category(recipe.category());
+			let state = state.clone();
+			let missing = missing.clone();
+
+			facilities.into_iter().map(move |facility| {
+				let mut next = state.clone();
+				let crafter = RecipeCrafter::with_goal(recipe.clone(), facility.clone(), &missing);
+				next.connectcrafter(crafter);
+				next
+			})
+		});
+
+		Box::new(successors)
+	})
+}
+
+// Picks the next non-ignored deficit out of a state and expands it,
+// recursing until no deficit remains -- that terminal state is one solution
+fn solvegoal<'a>(db: &'a DataBase<'a>, state: PartialFactory<'a>) -> Box<dyn Iterator<Item = PartialFactory<'a>> + 'a> {
+	match state.negativerates().into_iter().next() {
+		None => Box::new(std::iter::once(state)),
+		Some((product, _)) => {
+			// product borrows from state; clone it out before state moves
+			// into expandproduct(), or the borrow checker rejects the move
+			let product = product.clone();
+			let expanded = expandproduct(db, product)(state);
+			Box::new(expanded.flat_map(move |next| solvegoal(db, next)))
+		},
+	}
+}
+
+// Enumerates complete factory configurations satisfying goal's deficits,
+// as a lazy stream -- the caller decides how many solutions to pull and
+// how to rank them (see Factory::search() in factories.rs)
+pub fn search<'a>(db: &'a DataBase<'a>, start: PartialFactory<'a>) -> impl Iterator<Item = PartialFactory<'a>> + 'a {
+	solvegoal(db, start)
+}
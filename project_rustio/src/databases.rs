@@ -4,6 +4,9 @@
 use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 
+use num::Signed;
+
+use crate::dataloaders::DatabaseError;
 use crate::facilities::{FacilityCategory, Facility};
 use crate::products::Product;
 use crate::rates::{Rate, Time};
@@ -52,15 +55,28 @@ impl<'a> DataBase<'a> {
 /////////////////////////////////
 
 impl<'a> DataBase<'a> {
-	fn maketable_facilities(&'a mut self) {
+	// Builds both lookup tables in a single pass over the already-loaded
+	// data. Every entry in them borrows out of this same DataBase's own
+	// facilities/recipes maps, so those borrows can only be valid for 'a
+	// (this type's own invariant lifetime) rather than some ordinary,
+	// shorter elided lifetime -- which is why the receiver here is tied to
+	// 'a instead of being a plain &mut self. That in turn means this can
+	// only run once, through a borrow that already covers every later read
+	// of the tables (call it right after loaddatabase(), before taking any
+	// other reference to the DataBase), and both tables have to be filled
+	// in this one function body rather than split into separate &'a mut
+	// self helpers -- a second such call would need to reborrow self for
+	// the same 'a a second time, which the borrow checker won't allow.
+	pub fn maketables(&'a mut self) {
+		print!("Making lookup tables for DataBase...");
+		io::stdout().flush().expect("should be able to flush stdout");
+
 		for facility in self.facilities.values() {
 			let facilityset = self.lookuptable_facilities
 			    .entry(facility.category()).or_insert_with(HashSet::new);
 			facilityset.insert(facility);
 		}
-	}
 
-	fn maketable_recipes(&mut self) {
 		for recipe in self.recipes.values() {
 			for quantity in recipe.outputs() {
 				let recipeset = self.lookuptable_recipes
@@ -68,15 +84,7 @@ impl<'a> DataBase<'a> {
 				recipeset.insert(recipe);
 			}
 		}
-	}
 
-	pub fn maketables(&mut self) {
-		print!("Making lookup tables for DataBase...");
-		io::stdout().flush().expect("should be able to flush stdout");
-	
-		self.maketable_facilities();
-		self.maketable_recipes();
-	
 		println!(" done!");
 	}
 }
@@ -87,25 +95,131 @@ impl<'a> DataBase<'a> {
 //////////////////////////////
 
 impl<'a> DataBase<'a> {
-	pub fn findfacilities_category(&mut self, category: &FacilityCategory) -> &HashSet<&Facility> {
-		self.lookuptable_facilities.entry(category).or_insert_with(HashSet::new)
+	// Both of these only ever read the lookup tables built by maketables(),
+	// so they take a plain shared &self rather than tying the caller to
+	// 'a the way maketables() has to -- a category or product with no
+	// registered facilities/recipes is simply reported as an empty Vec
+	// rather than auto-vivifying an empty entry back into the table (doing
+	// that would require inserting a key valid for 'a, which a caller
+	// holding only a short-lived Product/FacilityCategory, like a freshly
+	// cloned Recipe's category, can never provide)
+	pub fn recipes_for_output(&self, recipeoutput: &Product) -> Vec<&Recipe> {
+		self.lookuptable_recipes.get(recipeoutput)
+		    .map(|recipeset| recipeset.iter().copied().collect())
+			.unwrap_or_default()
 	}
 
-	pub fn findfacilities_categoryname(&mut self, categoryname: &str) -> &HashSet<&Facility> {
-		let category = FacilityCategory::new(categoryname);
-		self.findfacilities_category(&category)
+	pub fn facilities_for_category(&self, category: &FacilityCategory) -> Vec<&Facility> {
+		self.lookuptable_facilities.get(category)
+		    .map(|facilityset| facilityset.iter().copied().collect())
+			.unwrap_or_default()
 	}
+}
 
-	pub fn findfacilities_recipe(&mut self, recipe: &Recipe) -> &HashSet<&Facility> {
-		self.findfacilities_category(recipe.category())
-	}
 
-	pub fn findrecipes_output(&mut self, recipeoutput: &Product) -> &HashSet<&Recipe> {
-		self.lookuptable_recipes.entry(recipeoutput).or_insert_with(HashSet::new)
+////////////////////////////
+// DataBase Validation Pass //
+////////////////////////////
+
+impl<'a> DataBase<'a> {
+	// Checks every cross-reference a recipe makes against the rest of the
+	// loaded data -- its madein category, its input/output products, a
+	// positive time period, and at least one facility to run it in -- and
+	// collects every violation found instead of stopping at the first one.
+	// Meant to run right after maketables(), as a diagnostic pass a modder
+	// editing the game-data files can run to see everything wrong at once
+	pub fn validate(&self) -> Vec<DatabaseError> {
+		let mut errors = Vec::new();
+
+		for recipe in self.recipes.values() {
+			if !self.facilitycategories.contains(recipe.category()) {
+				errors.push(DatabaseError::UnknownRecipeCategory {
+					recipe: recipe.name().to_owned(),
+					category: recipe.category().name().to_owned(),
+				});
+			} else if self.facilities_for_category(recipe.category()).is_empty() {
+				errors.push(DatabaseError::NoFacilityForCategory {
+					category: recipe.category().name().to_owned(),
+				});
+			}
+
+			if !recipe.period().seconds.is_positive() {
+				errors.push(DatabaseError::NonPositivePeriod {
+					recipe: recipe.name().to_owned(),
+					period: recipe.period().to_string(),
+				});
+			}
+
+			for quantity in recipe.outputs().iter().chain(recipe.inputs().iter()) {
+				if !self.products.contains(quantity.product()) {
+					errors.push(DatabaseError::UnknownRecipeProduct {
+						recipe: recipe.name().to_owned(),
+						product: quantity.name().to_owned(),
+					});
+				}
+			}
+		}
+
+		errors
 	}
+}
+
 
-	pub fn findrecipes_outputname(&mut self, recipeoutputname: &str) -> &HashSet<&Recipe> {
-		let product = Product::new(recipeoutputname);
-		self.findrecipes_output(&product)
+#[cfg(test)]
+mod integration_tests {
+	use super::*;
+
+	use num::rational::Rational64;
+
+	use crate::facilities::{Facility, FacilityCategory};
+	use crate::factories::Factory;
+	use crate::products::{Product, ProductQuantity};
+	use crate::rates::{Rate, Time};
+	use crate::recipes::Recipe;
+
+	// Builds a DataBase and Factory by hand (rather than through the file
+	// readers) and drives maketables()/validate()/solve() on it, so the
+	// lifetime plumbing tying them together is actually exercised rather
+	// than only the isolated gaussianeliminate() math. Raw ore (iron ore
+	// has no recipe of its own) is exactly what makes solve() converge via
+	// its own fixpoint loop and return early -- see factories.rs's own
+	// tests for balance(), which takes over only when solve() can't converge
+	#[test]
+	fn solves_and_validates_a_simple_chain() {
+		let mut db = DataBase::new();
+
+		let category = FacilityCategory::new("smelting facility");
+		db.facilitycategories.insert(category.clone());
+
+		let facility = Facility::new(category.clone(), String::new(), Rational64::new(1, 1));
+		db.facilities.insert(facility.name(), facility);
+
+		let ironore = Product::new("iron ore");
+		let ironingot = Product::new("iron ingot");
+		db.products.insert(ironore.clone());
+		db.products.insert(ironingot.clone());
+
+		let recipe = Recipe::with_name(
+			"smelt iron ingot",
+			vec![ProductQuantity::new(Rational64::new(1, 1), ironingot.clone())],
+			vec![ProductQuantity::new(Rational64::new(1, 1), ironore.clone())],
+			Time::new(Rational64::new(1, 1)),
+			category.clone(),
+		);
+		db.recipes.insert(recipe.name().to_owned(), recipe);
+
+		db.maketables();
+
+		assert!(db.validate().is_empty());
+
+		let goal = ProductQuantity::new(Rate::new(Rational64::new(2, 1)), ironingot.clone());
+		let mut factory = Factory::new(goal);
+		factory.solve(&db).expect("a simple one-recipe chain should solve");
+
+		assert_eq!(factory.crafters().len(), 1);
+		assert_eq!(
+			factory.rates().get(&ironore).copied(),
+			Some(-Rate::new(Rational64::new(2, 1)))
+		);
 	}
 }
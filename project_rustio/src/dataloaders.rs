@@ -1,9 +1,11 @@
 // dataloaders.rs
 // Functions for loading DSP data types from (mainly TOML) files into Julia
 
+use std::error;
+use std::fmt;
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use lazy_static::lazy_static;
 use num::FromPrimitive;
@@ -14,7 +16,7 @@ use crate::databases::DataBase;
 use crate::facilities::{FacilityCategory, Facility};
 use crate::products::Product;
 use crate::rates::{Time, Rate};
-use crate::recipereaders::{readrecipe, readrecipefile};
+use crate::recipereaders::{readrecipe, readrecipefile, RecipeParseError, Token};
 
 
 //////////////////////////////////
@@ -31,61 +33,208 @@ lazy_static! {
 }
 
 
+///////////////////////
+// DatabaseError Type //
+///////////////////////
+
+// Every way loading or validating the game-data files can go wrong, each
+// carrying enough context (the offending file, TOML key, and/or value) for
+// a modder editing those files to find and fix the problem directly,
+// instead of chasing a bare panic message up a backtrace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseError {
+	CannotReadFile { file: PathBuf, message: String },
+	CannotParseToml { file: PathBuf, message: String },
+	MissingKey { file: PathBuf, key: String },
+	MalformedValue { file: PathBuf, key: String, value: String },
+	DuplicateName { file: PathBuf, key: String, value: String },
+	RecipeParse(RecipeParseError),
+
+	// UnknownFacilityCategory is raised by loaddata_facility() while the
+	// facilities file loads; the rest are found by DataBase::validate()
+	// after every file has loaded, so all of those can be reported together
+	// instead of failing on the first one
+	UnknownFacilityCategory { facility: String, category: String },
+	UnknownRecipeCategory { recipe: String, category: String },
+	UnknownRecipeProduct { recipe: String, product: String },
+	NonPositivePeriod { recipe: String, period: String },
+	NoFacilityForCategory { category: String },
+}
+
+impl fmt::Display for DatabaseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DatabaseError::CannotReadFile { file, message } => {
+				write!(f, "unable to read {:?}: {}", file, message)
+			},
+			DatabaseError::CannotParseToml { file, message } => {
+				write!(f, "unable to parse {:?} as toml: {}", file, message)
+			},
+			DatabaseError::MissingKey { file, key } => {
+				write!(f, "{:?} is missing required key {:?}", file, key)
+			},
+			DatabaseError::MalformedValue { file, key, value } => {
+				write!(f, "{:?} has a malformed value for {:?}: {}", file, key, value)
+			},
+			DatabaseError::DuplicateName { file, key, value } => {
+				write!(f, "{:?} declares {:?} more than once under {:?}", file, value, key)
+			},
+			DatabaseError::RecipeParse(e) => write!(f, "{}", e),
+			DatabaseError::UnknownFacilityCategory { facility, category } => {
+				write!(f, "facility {:?} names unknown facility category {:?}", facility, category)
+			},
+			DatabaseError::UnknownRecipeCategory { recipe, category } => {
+				write!(f, "recipe {:?} is made in unknown facility category {:?}", recipe, category)
+			},
+			DatabaseError::UnknownRecipeProduct { recipe, product } => {
+				write!(f, "recipe {:?} references unknown product {:?}", recipe, product)
+			},
+			DatabaseError::NonPositivePeriod { recipe, period } => {
+				write!(f, "recipe {:?} has a non-positive time period: {}", recipe, period)
+			},
+			DatabaseError::NoFacilityForCategory { category } => {
+				write!(f, "facility category {:?} has no facilities to run its recipes in", category)
+			},
+		}
+	}
+}
+
+impl error::Error for DatabaseError {}
+
+impl From<RecipeParseError> for DatabaseError {
+	fn from(e: RecipeParseError) -> Self {
+		DatabaseError::RecipeParse(e)
+	}
+}
+
+
+///////////////////////////////
+// TOML Access Helper Functions //
+///////////////////////////////
+
+// These give every loaddata_*/load*data function below a uniform way to
+// pull a key out of a toml::Table (or coerce a toml::Value to the shape it
+// expects) and turn a miss into a DatabaseError instead of a panic
+
+fn tomlget<'t>(table: &'t toml::Table, file: &Path, key: &str) -> Result<&'t toml::Value, DatabaseError> {
+	table.get(key).ok_or_else(|| DatabaseError::MissingKey {
+		file: file.to_path_buf(),
+		key: key.to_owned(),
+	})
+}
+
+fn tomlstr<'t>(value: &'t toml::Value, file: &Path, key: &str) -> Result<&'t str, DatabaseError> {
+	value.as_str().ok_or_else(|| DatabaseError::MalformedValue {
+		file: file.to_path_buf(),
+		key: key.to_owned(),
+		value: value.to_string(),
+	})
+}
+
+fn tomlarray<'t>(value: &'t toml::Value, file: &Path, key: &str) -> Result<&'t Vec<toml::Value>, DatabaseError> {
+	value.as_array().ok_or_else(|| DatabaseError::MalformedValue {
+		file: file.to_path_buf(),
+		key: key.to_owned(),
+		value: value.to_string(),
+	})
+}
+
+fn tomltableval<'t>(value: &'t toml::Value, file: &Path, key: &str) -> Result<&'t toml::Table, DatabaseError> {
+	value.as_table().ok_or_else(|| DatabaseError::MalformedValue {
+		file: file.to_path_buf(),
+		key: key.to_owned(),
+		value: value.to_string(),
+	})
+}
+
+fn readtomlfile(file: &Path) -> Result<toml::Table, DatabaseError> {
+	let contents = fs::read_to_string(file).map_err(|e| DatabaseError::CannotReadFile {
+		file: file.to_path_buf(),
+		message: e.to_string(),
+	})?;
+	contents.parse::<toml::Table>().map_err(|e| DatabaseError::CannotParseToml {
+		file: file.to_path_buf(),
+		message: e.to_string(),
+	})
+}
+
+
 ///////////////////////////////////////
 // Facilities Data Loading Functions //
 ///////////////////////////////////////
 
-fn loaddata_facilitycategory(db: &mut DataBase, tomldata: &str) {
+fn loaddata_facilitycategory(db: &mut DataBase, file: &Path, tomldata: &str) -> Result<(), DatabaseError> {
 	let fc_name = tomldata;
 	let fc = FacilityCategory::new(fc_name);
-	
+
 	if db.facilitycategories.contains(&fc) {
-		panic!("duplicate facility category name: {:?}", fc.name());
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "facility categories".to_owned(),
+			value: fc.name().to_owned(),
+		});
 	}
 	db.facilitycategories.insert(fc);
+	Ok(())
 }
 
-fn loaddata_facility(db: &mut DataBase, tomldata: &toml::Table) {
-	let f_categoryname = tomldata["category"].as_str().unwrap();
-	let f_adjective = tomldata["adjective"].as_str().unwrap();
-	let f_speed = match &tomldata["speed"] {
-		&toml::Value::Integer(i) => Rational64::new(i, 1),
-		&toml::Value::Float(f) => Rational64::from_f64(f).unwrap(),
-		v => panic!("invalid facility speed in toml: {:?}", v),
+fn loaddata_facility(db: &mut DataBase, file: &Path, tomldata: &toml::Table) -> Result<(), DatabaseError> {
+	let f_categoryname = tomlstr(tomlget(tomldata, file, "category")?, file, "category")?;
+	let f_adjective = tomlstr(tomlget(tomldata, file, "adjective")?, file, "adjective")?;
+	let f_speedvalue = tomlget(tomldata, file, "speed")?;
+	let f_speed = match f_speedvalue {
+		toml::Value::Integer(i) => Rational64::new(*i, 1),
+		toml::Value::Float(x) => Rational64::from_f64(*x).ok_or_else(|| DatabaseError::MalformedValue {
+			file: file.to_path_buf(),
+			key: "speed".to_owned(),
+			value: f_speedvalue.to_string(),
+		})?,
+		v => return Err(DatabaseError::MalformedValue {
+			file: file.to_path_buf(),
+			key: "speed".to_owned(),
+			value: v.to_string(),
+		}),
 	};
-	
+
 	let f = Facility::with_categoryname(
 		f_categoryname,
 		f_adjective.to_owned(),
 		f_speed
 	);
 	let f_name = f.name();
-	
+
 	if db.facilities.contains_key(&f_name) {
-		panic!("duplicate facility name: {:?}", &f_name);
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "facilities".to_owned(),
+			value: f_name,
+		});
 	}
-	if db.facilitycategories.contains(f.category()) {
-		panic!("unknown facility category: {:?}", f.categoryname());
+	if !db.facilitycategories.contains(f.category()) {
+		return Err(DatabaseError::UnknownFacilityCategory {
+			facility: f_name,
+			category: f.categoryname().to_owned(),
+		});
 	}
 	db.facilities.insert(f_name, f);
+	Ok(())
 }
 
-fn loadfacilitiesdata(db: &mut DataBase, tomlfilename: PathBuf) {
-	
-	let tomltable = fs::read_to_string(tomlfilename)
-	    .expect("should be able to open toml file")
-		.parse::<toml::Table>()
-		.unwrap();
-	
-	for categorydata in tomltable["facility categories"].as_array().unwrap() {
-		let categorydata = categorydata.as_str().unwrap();
-		loaddata_facilitycategory(db, categorydata);
+fn loadfacilitiesdata(db: &mut DataBase, tomlfilename: PathBuf) -> Result<(), DatabaseError> {
+
+	let tomltable = readtomlfile(&tomlfilename)?;
+
+	for categorydata in tomlarray(tomlget(&tomltable, &tomlfilename, "facility categories")?, &tomlfilename, "facility categories")? {
+		let categorydata = tomlstr(categorydata, &tomlfilename, "facility categories")?;
+		loaddata_facilitycategory(db, &tomlfilename, categorydata)?;
 	}
-	
-	for facilitydata in tomltable["facilities"].as_array().unwrap() {
-		let facilitydata = facilitydata.as_table().unwrap();
-		loaddata_facility(db, facilitydata);
+
+	for facilitydata in tomlarray(tomlget(&tomltable, &tomlfilename, "facilities")?, &tomlfilename, "facilities")? {
+		let facilitydata = tomltableval(facilitydata, &tomlfilename, "facilities")?;
+		loaddata_facility(db, &tomlfilename, facilitydata)?;
 	}
+
+	Ok(())
 }
 
 
@@ -93,27 +242,31 @@ fn loadfacilitiesdata(db: &mut DataBase, tomlfilename: PathBuf) {
 // Products Data Loading Functions //
 /////////////////////////////////////
 
-fn loaddata_product(db: &mut DataBase, tomldata: &str) {
+fn loaddata_product(db: &mut DataBase, file: &Path, tomldata: &str) -> Result<(), DatabaseError> {
 	let p_name = tomldata;
 	let p = Product::new(p_name);
-	
+
 	if db.products.contains(&p) {
-		panic!("duplicate product name: {:?}", p.name());
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "products".to_owned(),
+			value: p.name().to_owned(),
+		});
 	}
 	db.products.insert(p);
+	Ok(())
 }
 
-fn loadproductsdata(db: &mut DataBase, tomlfilename: PathBuf) {
-	
-	let tomltable = fs::read_to_string(tomlfilename)
-	    .expect("should be able to open toml file")
-		.parse::<toml::Table>()
-		.unwrap();
-	
-	for productdata in tomltable["products"].as_array().unwrap() {
-		let productdata = productdata.as_str().unwrap();
-		loaddata_product(db, productdata);
+fn loadproductsdata(db: &mut DataBase, tomlfilename: PathBuf) -> Result<(), DatabaseError> {
+
+	let tomltable = readtomlfile(&tomlfilename)?;
+
+	for productdata in tomlarray(tomlget(&tomltable, &tomlfilename, "products")?, &tomlfilename, "products")? {
+		let productdata = tomlstr(productdata, &tomlfilename, "products")?;
+		loaddata_product(db, &tomlfilename, productdata)?;
 	}
+
+	Ok(())
 }
 
 
@@ -123,24 +276,31 @@ fn loadproductsdata(db: &mut DataBase, tomlfilename: PathBuf) {
 
 // Implementations of readrecipe() and readrecipefile() are in recipereaders.rs
 
-fn loaddata_recipe(db: &mut DataBase, recipedata: &Vec<String>) {
+fn loaddata_recipe(db: &mut DataBase, file: &Path, recipedata: &[Token]) -> Result<(), DatabaseError> {
 	let recipeblock = recipedata;
-	let r = readrecipe(recipeblock);
-	
+	let r = readrecipe(recipeblock)?;
+
 	if db.recipes.contains_key(r.name()) {
-		panic!("duplicate recipe name: {:?}", r.name());
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "recipes".to_owned(),
+			value: r.name().to_owned(),
+		});
 	}
 	db.recipes.insert(r.name().to_owned(), r);
+	Ok(())
 }
 
-fn loadrecipesdata(db: &mut DataBase, datafilename: PathBuf) {
-	
+fn loadrecipesdata(db: &mut DataBase, datafilename: PathBuf) -> Result<(), DatabaseError> {
+
 	// Pass in the database so it can be used to resolve abbreviations
-	let recipetable = readrecipefile(datafilename, db);
-	
+	let recipetable = readrecipefile(datafilename.clone(), db)?;
+
 	for recipedata in recipetable.iter() {
-		loaddata_recipe(db, recipedata);
+		loaddata_recipe(db, &datafilename, recipedata)?;
 	}
+
+	Ok(())
 }
 
 
@@ -148,48 +308,69 @@ fn loadrecipesdata(db: &mut DataBase, datafilename: PathBuf) {
 // Rates Data Loading Functions //
 //////////////////////////////////
 
-fn loaddata_rate(db: &mut DataBase, tomldata: (&str, &toml::Value)) {
+fn loaddata_rate(db: &mut DataBase, file: &Path, tomldata: (&str, &toml::Value)) -> Result<(), DatabaseError> {
 	let ratename = tomldata.0;
-	let r_persecond = tomldata.1.as_integer().unwrap();
+	let r_persecond = tomldata.1.as_integer().ok_or_else(|| DatabaseError::MalformedValue {
+		file: file.to_path_buf(),
+		key: format!("rates.{}", ratename),
+		value: tomldata.1.to_string(),
+	})?;
 	let r = Rate::new(Rational64::new(r_persecond, 1));
-	
+
 	if db.rates.contains_key(ratename) {
-		panic!("duplicate rate name: {:?}", ratename);
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "rates".to_owned(),
+			value: ratename.to_owned(),
+		});
 	}
 	db.rates.insert(ratename.to_owned(), r);
+	Ok(())
 }
 
-fn loaddata_time(db: &mut DataBase, tomldata: (&str, &toml::Value)) {
+fn loaddata_time(db: &mut DataBase, file: &Path, tomldata: (&str, &toml::Value)) -> Result<(), DatabaseError> {
 	let timename = tomldata.0;
-	let t_seconds = tomldata.1.as_integer().unwrap();
+	let t_seconds = tomldata.1.as_integer().ok_or_else(|| DatabaseError::MalformedValue {
+		file: file.to_path_buf(),
+		key: format!("times.{}", timename),
+		value: tomldata.1.to_string(),
+	})?;
 	let ratename = format!("/{}", timename);
 	let t = Time::new(Rational64::new(t_seconds, 1));
 	let r = Rate::from(t);
-	
+
 	if db.times.contains_key(timename) {
-		panic!("duplicate time name: {:?}", timename);
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "times".to_owned(),
+			value: timename.to_owned(),
+		});
 	}
 	if db.rates.contains_key(&ratename) {
-		panic!("duplicate rate name was generated: {:?}", ratename);
+		return Err(DatabaseError::DuplicateName {
+			file: file.to_path_buf(),
+			key: "rates".to_owned(),
+			value: ratename,
+		});
 	}
 	db.times.insert(timename.to_owned(), t);
 	db.rates.insert(ratename, r);
+	Ok(())
 }
 
-fn loadratesdata(db: &mut DataBase, tomlfilename: PathBuf) {
-	
-	let tomltable = fs::read_to_string(tomlfilename)
-	    .expect("should be able to open toml file")
-		.parse::<toml::Table>()
-		.unwrap();
-	
-	for ratedata in tomltable["rates"].as_table().unwrap() {
-		loaddata_rate(db, (ratedata.0, ratedata.1));
+fn loadratesdata(db: &mut DataBase, tomlfilename: PathBuf) -> Result<(), DatabaseError> {
+
+	let tomltable = readtomlfile(&tomlfilename)?;
+
+	for ratedata in tomltableval(tomlget(&tomltable, &tomlfilename, "rates")?, &tomlfilename, "rates")? {
+		loaddata_rate(db, &tomlfilename, (ratedata.0, ratedata.1))?;
 	}
-	
-	for timedata in tomltable["times"].as_table().unwrap() {
-		loaddata_time(db, (timedata.0, timedata.1));
+
+	for timedata in tomltableval(tomlget(&tomltable, &tomlfilename, "times")?, &tomlfilename, "times")? {
+		loaddata_time(db, &tomlfilename, (timedata.0, timedata.1))?;
 	}
+
+	Ok(())
 }
 
 
@@ -197,15 +378,16 @@ fn loadratesdata(db: &mut DataBase, tomlfilename: PathBuf) {
 // Top-Level DataBase Loading Function //
 /////////////////////////////////////////
 
-pub fn loaddatabase(db: &mut DataBase) {
-	
+pub fn loaddatabase(db: &mut DataBase) -> Result<(), DatabaseError> {
+
 	print!("Loading contents of DataBase from files...");
 	io::stdout().flush().expect("should be able to flush stdout");
-	
-	loadfacilitiesdata(db, FACILITIESFILENAME.to_path_buf());
-	loadproductsdata(db, PRODUCTSFILENAME.to_path_buf());
-	loadrecipesdata(db, RECIPESFILENAME.to_path_buf());
-	loadratesdata(db, RATESFILENAME.to_path_buf());
-	
+
+	loadfacilitiesdata(db, FACILITIESFILENAME.to_path_buf())?;
+	loadproductsdata(db, PRODUCTSFILENAME.to_path_buf())?;
+	loadrecipesdata(db, RECIPESFILENAME.to_path_buf())?;
+	loadratesdata(db, RATESFILENAME.to_path_buf())?;
+
 	println!(" done!");
+	Ok(())
 }
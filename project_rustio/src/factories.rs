@@ -7,6 +7,7 @@ use std::fmt;
 use num::rational::Rational64;
 use num::traits::Zero;
 
+use crate::databases::DataBase;
 use crate::facilities::Facility;
 use crate::products::{Product, ProductQuantity};
 use crate::rationalutilities::prettystring;
@@ -14,6 +15,59 @@ use crate::rates::Rate;
 use crate::recipes::Recipe;
 
 
+/////////////////////////////////////////
+// Proliferator Type Implementation //
+/////////////////////////////////////////
+
+// A proliferator sprayed on a recipe crafter either boosts its output or
+// its speed, at the cost of consuming some of the spray product itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProliferationMode {
+	None,
+	ExtraProducts,
+	ProductionSpeedup,
+}
+
+// extrabonus and speedbonus are the fractional bonus (e.g. 1/4 for +25%)
+// that applies in ExtraProducts and ProductionSpeedup mode respectively
+// itemspercharge is how many processed items one spray charge covers, used
+// to translate item throughput into a consumption rate of sprayproduct
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proliferator<'a> {
+	sprayproduct: &'a Product,
+	extrabonus: Rational64,
+	speedbonus: Rational64,
+	itemspercharge: Rational64,
+}
+
+impl<'a> Proliferator<'a> {
+	pub fn new(
+		sprayproduct: &'a Product,
+		extrabonus: Rational64,
+		speedbonus: Rational64,
+		itemspercharge: Rational64,
+	) -> Self {
+		Self { sprayproduct, extrabonus, speedbonus, itemspercharge }
+	}
+
+	pub fn sprayproduct(&self) -> &'a Product {
+		self.sprayproduct
+	}
+
+	pub fn extrabonus(&self) -> Rational64 {
+		self.extrabonus
+	}
+
+	pub fn speedbonus(&self) -> Rational64 {
+		self.speedbonus
+	}
+
+	pub fn itemspercharge(&self) -> Rational64 {
+		self.itemspercharge
+	}
+}
+
+
 ///////////////////////////////////////
 // RecipeCrafter Type Implementation //
 ///////////////////////////////////////
@@ -21,53 +75,99 @@ use crate::recipes::Recipe;
 // When playing DSP, the number of facilities built obviously must be an Int
 // However, we live in theory land where everything is perfectly "at ratio"
 // To translate this fantasy number back into the DSP world, use ceil(howmany)
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RecipeCrafter<'a> {
 	recipe: Recipe,
 	facility: Facility,
 	howmany: Rational64,  // Int in DSP terms
-	
-	// TODO: proliferation info for recipe inputs
-	// ::Proliferator
-	// ::ProliferationMode
-	
-	rates: HashMap<&'a Product, Rate>,
-}	
+
+	proliferator: Option<Proliferator<'a>>,
+	proliferationmode: ProliferationMode,
+
+	// Keyed by an owned clone of each product rather than a &'a Product:
+	// every entry here comes from this crafter's own (owned) recipe, so a
+	// borrowed key could never honestly outlive a single &mut self call,
+	// let alone the struct's whole 'a
+	rates: HashMap<Product, Rate>,
+}
 
 impl<'a> RecipeCrafter<'a> {
 	pub fn new(recipe: Recipe, facility: Facility, howmany: Rational64) -> Self {
 		if howmany <= Rational64::zero() {
 			panic!("recipe producer should not have howmany <= 0");
 		}
-		Self {
+		let mut rc = Self {
 			recipe,
 			facility,
 			howmany,
+			proliferator: None,
+			proliferationmode: ProliferationMode::None,
 			rates: HashMap::new(),
-		}
+		};
+		rc.computerates();
+		rc
 	}
 
-	// This constructor takes a production rate and computes the value of howmany
-	pub fn with_goal(recipe: Recipe, facility: Facility, goal: &ProductQuantity<Rate>) -> Self {
-	
+	// Shared by with_goal() and with_goal_proliferated(): scales howmany so
+	// production of the goal product exactly matches the requested rate,
+	// under whatever proliferation settings are already on the crafter
+	fn size_to_goal(&mut self, goal: &ProductQuantity<Rate>) {
 		let (p, rate) = (goal.product(), goal.quantity());
-		let howmany = Rational64::new(1, 1);  // Temp value of 1
-		let mut rc = Self::new(recipe, facility, howmany);
-	
-		match rc.rates().get(p) {
+
+		match self.rates().get(p) {
 			Some(&r) if r > Rate::zero() => {
 				let rateratio = rate / r;
-				rc.howmany *= rateratio;
+				self.howmany *= rateratio;
 			},
 			_ => panic!("given recipe does not produce goal product"),
 		}
-	
-		// TODO: consider proliferation of the recipe crafter
-	
-		rc.computerates();
+
+		self.computerates();
+	}
+
+	// This constructor takes a production rate and computes the value of howmany
+	pub fn with_goal(recipe: Recipe, facility: Facility, goal: &ProductQuantity<Rate>) -> Self {
+		let mut rc = Self::new(recipe, facility, Rational64::new(1, 1));
+		rc.size_to_goal(goal);
+		rc
+	}
+
+	// Like with_goal(), but sizes howmany with the proliferator already
+	// applied, so ExtraProducts mode's output bonus is accounted for and
+	// the crafter hits the goal rate exactly rather than overshooting it
+	pub fn with_goal_proliferated(
+		recipe: Recipe,
+		facility: Facility,
+		goal: &ProductQuantity<Rate>,
+		proliferator: Proliferator<'a>,
+		mode: ProliferationMode,
+	) -> Self {
+		let mut rc = Self::new(recipe, facility, Rational64::new(1, 1));
+		rc.set_proliferation(proliferator, mode);
+		rc.size_to_goal(goal);
 		rc
 	}
 
+	pub fn set_proliferation(&mut self, proliferator: Proliferator<'a>, mode: ProliferationMode) {
+		self.proliferator = Some(proliferator);
+		self.proliferationmode = mode;
+		self.computerates();
+	}
+
+	pub fn clear_proliferation(&mut self) {
+		self.proliferator = None;
+		self.proliferationmode = ProliferationMode::None;
+		self.computerates();
+	}
+
+	pub fn proliferator(&self) -> Option<&Proliferator<'a>> {
+		self.proliferator.as_ref()
+	}
+
+	pub fn proliferationmode(&self) -> ProliferationMode {
+		self.proliferationmode
+	}
+
 	pub fn recipe(&self) -> &Recipe {
 		&self.recipe
 	}
@@ -85,33 +185,107 @@ impl<'a> RecipeCrafter<'a> {
 	// Use get!() here so the output rate will not be overwritten by the input rate
 	fn computerates(&mut self) {
 		self.rates.clear();
-	
+
+		let one = Rational64::new(1, 1);
+		let speedmultiplier = match self.proliferationmode {
+			ProliferationMode::ProductionSpeedup => {
+				one + self.proliferator.as_ref().map_or(Rational64::zero(), Proliferator::speedbonus)
+			},
+			_ => one,
+		};
+		let extramultiplier = match self.proliferationmode {
+			ProliferationMode::ExtraProducts => {
+				one + self.proliferator.as_ref().map_or(Rational64::zero(), Proliferator::extrabonus)
+			},
+			_ => one,
+		};
+
+		let mut itemspersecond = Rational64::zero();
+
 		for output in self.recipe().outputs() {
 			let reciperate = output.quantity() / self.recipe().period();
-			let totalrate = reciperate * self.facility().speed() * self.howmany();
+			let totalrate =
+				reciperate * self.facility().speed() * speedmultiplier * self.howmany() * extramultiplier;
+			itemspersecond += totalrate.persecond;
 			let accum = self.rates
-			    .entry(output.product()).or_insert(Rate::zero());
+			    .entry(output.product().clone()).or_insert(Rate::zero());
 			*accum += totalrate;
 		}
-	
+
 		for input in self.recipe().inputs() {
 			let reciperate = input.quantity() / self.recipe().period();
-			let totalrate = reciperate * self.facility().speed() * self.howmany();
+			let totalrate = reciperate * self.facility().speed() * speedmultiplier * self.howmany();
 			let accum = self.rates
-			    .entry(input.product()).or_insert(Rate::zero());
+			    .entry(input.product().clone()).or_insert(Rate::zero());
 			*accum -= totalrate;
 		}
-	
-		// TODO: proliferation would affect all these recipe rates
-		// *and* it would consume proliferator product at a certain rate
-	}
 
-	pub fn rates(&mut self) -> &HashMap<&Product, Rate> {
-		if self.rates.is_empty() {
-			self.computerates();
+		if self.proliferationmode != ProliferationMode::None {
+			if let Some(proliferator) = &self.proliferator {
+				let consumptionrate = Rate::new(itemspersecond / proliferator.itemspercharge());
+				let accum = self.rates
+				    .entry(proliferator.sprayproduct().clone()).or_insert(Rate::zero());
+				*accum -= consumptionrate;
+			}
 		}
+	}
+
+	// Read-only: computerates() is called at construction and after every
+	// mutation (size_to_goal, set_proliferation, clear_proliferation), so
+	// the table is always current and this never needs to rebuild it (and
+	// never needs more than a shared borrow to hand it back out)
+	pub fn rates(&self) -> &HashMap<Product, Rate> {
 		&self.rates
 	}
+
+	// The net rate this crafter contributes to product p per single unit
+	// of howmany, i.e. what computerates() would produce if howmany were 1
+	// This is exactly the coefficient balance() needs for its linear system,
+	// so it has to apply the same proliferation multipliers and spray-product
+	// consumption term computerates() does -- otherwise balance() would size
+	// a proliferated crafter's howmany as if proliferation weren't active
+	fn unitrate(&self, p: &Product) -> Rate {
+		let one = Rational64::new(1, 1);
+		let speedmultiplier = match self.proliferationmode {
+			ProliferationMode::ProductionSpeedup => {
+				one + self.proliferator.as_ref().map_or(Rational64::zero(), Proliferator::speedbonus)
+			},
+			_ => one,
+		};
+		let extramultiplier = match self.proliferationmode {
+			ProliferationMode::ExtraProducts => {
+				one + self.proliferator.as_ref().map_or(Rational64::zero(), Proliferator::extrabonus)
+			},
+			_ => one,
+		};
+
+		let mut rate = Rate::zero();
+		let mut itemspersecond = Rational64::zero();
+
+		for output in self.recipe().outputs() {
+			let outputrate =
+				(output.quantity() / self.recipe().period()) * self.facility().speed() * speedmultiplier * extramultiplier;
+			itemspersecond += outputrate.persecond;
+			if output.product() == p {
+				rate += outputrate;
+			}
+		}
+		for input in self.recipe().inputs() {
+			if input.product() == p {
+				rate -= (input.quantity() / self.recipe().period()) * self.facility().speed() * speedmultiplier;
+			}
+		}
+
+		if self.proliferationmode != ProliferationMode::None {
+			if let Some(proliferator) = &self.proliferator {
+				if proliferator.sprayproduct() == p {
+					rate -= Rate::new(itemspersecond / proliferator.itemspercharge());
+				}
+			}
+		}
+
+		rate
+	}
 }
 
 impl<'a> fmt::Display for RecipeCrafter<'a> {
@@ -131,7 +305,7 @@ impl<'a> fmt::Display for RecipeCrafter<'a> {
 // Factory Type Implementation //
 /////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Factory<'a> {
 	goal: ProductQuantity<Rate>,
 	crafters: Vec<RecipeCrafter<'a>>,
@@ -139,18 +313,24 @@ pub struct Factory<'a> {
 	// TODO: output proflieration info for primary product
 	// ::Proliferator
 	
-	rates: HashMap<&'a Product, Rate>,
-	ignoredrates: HashMap<&'a Product, bool>,  // If true, ignore negative rates
-}	
+	// Owned Product keys for the same reason as RecipeCrafter::rates above:
+	// every entry traces back to either this factory's own goal or one of
+	// its crafters' own recipes, never to anything that could honestly
+	// live for this struct's whole 'a
+	rates: HashMap<Product, Rate>,
+	ignoredrates: HashMap<Product, bool>,  // If true, ignore negative rates
+}
 
 impl<'a> Factory<'a> {
 	pub fn new(goal: ProductQuantity<Rate>) -> Self {
-		Self {
+		let mut factory = Self {
 			goal,
 			crafters: Vec::new(),
 			rates: HashMap::new(),
 			ignoredrates: HashMap::new(),
-		}
+		};
+		factory.computerates();
+		factory
 	}
 
 	pub fn goal(&self) -> &ProductQuantity<Rate> {
@@ -163,32 +343,33 @@ impl<'a> Factory<'a> {
 
 	fn computerates(&mut self) {
 		self.rates.clear();
-	
+
 		self.rates.insert(
-			self.goal().product(),
+			self.goal().product().clone(),
 			-self.goal().quantity()
 		);
 
 		for crafter in self.crafters() {
 			for (product, &rate) in crafter.rates() {
 				let accum = self.rates
-				    .entry(product).or_insert(Rate::zero());
+				    .entry(product.clone()).or_insert(Rate::zero());
 				*accum += rate;
 			}
 		}
 	}
 
-	pub fn rates(&mut self) -> &HashMap<&Product, Rate> {
-		if self.rates.is_empty() {
-			self.computerates();
-		}
+	// Read-only: computerates() runs at construction and after every
+	// mutation (connectcrafter, upgradecrafter, balance), so the table is
+	// always current and this never needs more than a shared borrow to
+	// hand it back out
+	pub fn rates(&self) -> &HashMap<Product, Rate> {
 		&self.rates
 	}
 
-	pub fn isignored(&mut self, p: &Product) -> bool {
-		match self.rates().get(p) {
+	pub fn isignored(&self, p: &Product) -> bool {
+		match self.rates.get(p) {
 			None => panic!("product must exist to check if ignored"),
-			_ => *self.ignoredrates.entry(p).or_insert(false),
+			_ => self.ignoredrates.get(p).copied().unwrap_or(false),
 		}
 	}
 
@@ -200,7 +381,7 @@ impl<'a> Factory<'a> {
 			.filter(|(product, &productrate)| {
 				!self.isignored(product) && productrate < Rate::zero()
 			})
-			.map(|(&product, &productrate)| {
+			.map(|(product, &productrate)| {
 				(product, productrate)
 			})
 			.collect()
@@ -247,11 +428,11 @@ impl<'a> fmt::Display for Factory<'a> {
 			io.push_str("(none)");
 		}
 
-		let inputs: Vec<(&Product, Rate)> = self
+		let inputs: Vec<(Product, Rate)> = self
 			.rates()
 			.iter()
-			.filter(|(product, &rate)| rate < Rate::zero())
-			.map(|(&product, &rate)| (product, rate))
+			.filter(|(_, &rate)| rate < Rate::zero())
+			.map(|(product, &rate)| (product.clone(), rate))
 			.collect();
 
 		io.push_str("\n");
@@ -266,7 +447,7 @@ impl<'a> fmt::Display for Factory<'a> {
 			io.push_str(&indent);
 			let quantity = ProductQuantity::new(
 				-(*productrate),
-				*product.clone()
+				product.clone()
 			);
 			io.push_str(&quantity.to_string());
 		}
@@ -278,11 +459,11 @@ impl<'a> fmt::Display for Factory<'a> {
 			io.push_str("(none)");
 		}
 
-		let byproducts: Vec<(&Product, Rate)> = self
+		let byproducts: Vec<(Product, Rate)> = self
 			.rates()
 			.iter()
-			.filter(|(product, &rate)| rate > Rate::zero())
-			.map(|(product, rate)| (product.clone(), rate.clone()))
+			.filter(|(_, &rate)| rate > Rate::zero())
+			.map(|(product, &rate)| (product.clone(), rate))
 			.collect();
 
 		io.push_str("\n");
@@ -297,7 +478,7 @@ impl<'a> fmt::Display for Factory<'a> {
 			io.push_str(&indent);
 			let quantity = ProductQuantity::new(
 				*productrate,
-				*product.clone()
+				product.clone()
 			);
 			io.push_str(&quantity.to_string());
 		}
@@ -320,16 +501,20 @@ impl<'a> fmt::Display for Factory<'a> {
 
 impl<'a> Factory<'a> {
 	pub fn setignored(&mut self, p: &Product) {
-		match self.rates().get_key_value(p) {
+		// .rates field accessed directly (not via the rates() getter) so
+		// this borrow only covers that one field, leaving self.ignoredrates
+		// free to be mutated right below it in the same statement's scope
+		match self.rates.get_key_value(p) {
 			None => panic!("product must exist to be set as ignored"),
-			Some((&p, _)) => {
+			Some((p, _)) => {
+				let p = p.clone();
 				self.ignoredrates.insert(p, true);
 			},
 		}
 	}
 
 	pub fn setallignored(&mut self) {
-		for product in self.rates().keys() {
+		for product in self.rates.keys() {
 			self.ignoredrates.insert(product.clone(), true);
 		}
 	}
@@ -342,36 +527,428 @@ impl<'a> Factory<'a> {
 		self.computerates();
 	}
 
+	// Upgrades the crafter already connected at the given index in place,
+	// rescaling it so its production of the target product increases by
+	// exactly productionincrease. Indexing in rather than taking the
+	// crafter by value lets callers (like solve() below) look a crafter up
+	// by the product it makes and grow it without detaching it first
 	pub fn upgradecrafter(
 		&mut self,
-		mut rc: RecipeCrafter,
+		index: usize,
 		productionincrease: ProductQuantity<Rate>
 	) {
-		
+
 		let target = productionincrease.product();
 		let delta_rate = productionincrease.quantity();
-		
-		if !self.crafters().contains(&rc) {
-			panic!("crafter must be factory-connected to upgrade");
-		}
 
-		let rc_rates_target = rc.rates().get(target);
-		if rc_rates_target.is_none() || *rc_rates_target.unwrap() <= Rate::zero() {
+		let rc = self.crafters.get_mut(index)
+			.unwrap_or_else(|| panic!("no crafter connected at index {}", index));
+
+		let rc_rates_target = rc.rates().get(target).copied();
+		if rc_rates_target.is_none() || rc_rates_target.unwrap() <= Rate::zero() {
 			panic!("upgrading crafter does not produce target product");
 		}
 
 		let rc_rates_target = rc_rates_target.unwrap();
-		if *rc_rates_target + delta_rate <= Rate::zero() {
+		if rc_rates_target + delta_rate <= Rate::zero() {
 			panic!("final upgraded crafter rate must be positive");
 		}
-		
-		let upgraderatio = (*rc_rates_target + delta_rate) / *rc_rates_target;
-		
+
+		let upgraderatio = (rc_rates_target + delta_rate) / rc_rates_target;
+
 		rc.howmany *= upgraderatio;
-		
+
 		// TODO: consider how the proliferation of the crafter is to be upgraded
-		
+
 		rc.computerates();
 		self.computerates();
 	}
+
+	// The index of the crafter (if any) already connected that produces p,
+	// for reuse by callers that want to grow existing production instead
+	// of connecting a brand new duplicate crafter for the same product
+	fn findcrafterindex(&self, p: &Product) -> Option<usize> {
+		let existing = *self.findcrafters(p).first()?;
+		self.crafters.iter().position(|rc| rc == existing)
+	}
+}
+
+
+////////////////////////////////////////
+// Automatic Production-Chain Solver //
+////////////////////////////////////////
+
+impl<'a> Factory<'a> {
+	// Hard cap on how many fixpoint rounds to run before concluding the
+	// deficits aren't converging and handing off to balance() instead
+	const SOLVEITERATIONLIMIT: usize = 1000;
+
+	// Drives a fixpoint over the rate table: as long as some non-ignored
+	// product still has a net deficit, pick a recipe and facility to make
+	// up exactly the missing rate, growing an already-connected crafter
+	// via upgradecrafter() if one exists for that product, or connecting
+	// a freshly sized one via with_goal() otherwise. Products with no
+	// known recipe (raw ores) are auto-ignored rather than left failing.
+	//
+	// A genuine recipe loop (e.g. two recipes that each consume the
+	// other's output) never satisfies this fixpoint exactly: every round
+	// cancels the current round's deficit precisely, but the loop reopens
+	// a smaller, nonzero deficit elsewhere, forever. Rather than spin on
+	// that geometrically-shrinking residual, this bails out after
+	// SOLVEITERATIONLIMIT rounds and hands the whole factory to balance(),
+	// which solves any such loop exactly via linear algebra instead of
+	// approaching it one vanishing step at a time.
+	pub fn solve(&mut self, db: &DataBase<'a>) -> Result<(), BalanceError> {
+		for _ in 0..Self::SOLVEITERATIONLIMIT {
+			let deficits: Vec<(Product, Rate)> = self.negativerates()
+				.into_iter()
+				.map(|(product, rate)| (product.clone(), rate))
+				.collect();
+
+			if deficits.is_empty() {
+				return Ok(());
+			}
+
+			let mut progressed = false;
+
+			for (product, _) in deficits {
+				if self.isignored(&product) {
+					continue;
+				}
+
+				// A product's rate can shift out from under this snapshot
+				// partway through the round -- e.g. an earlier entry's
+				// crafter has this product as a byproduct, or shares a
+				// crafter with it -- so the deficit has to be re-read right
+				// before acting on it. Anything already resolved (or now a
+				// surplus) by an earlier entry this round is skipped rather
+				// than acted on with a stale number, since upgradecrafter()
+				// can only grow a crafter and has no way to walk one back
+				let rate = match self.rates().get(&product).copied() {
+					Some(r) if r < Rate::zero() => r,
+					_ => continue,
+				};
+
+				let candidaterecipes = db.recipes_for_output(&product);
+				if candidaterecipes.is_empty() {
+					self.setignored(&product);
+					progressed = true;
+					continue;
+				}
+				let recipe = (**candidaterecipes.iter()
+					.min_by_key(|r| r.name())
+					.unwrap()).clone();
+
+				let candidatefacilities = db.facilities_for_category(recipe.category());
+				let facility = (**candidatefacilities.iter()
+					.min_by_key(|f| f.name())
+					.unwrap_or_else(|| panic!(
+						"no facility exists to craft recipe: {:?}", recipe.name()
+					))).clone();
+
+				let missing = ProductQuantity::new(-rate, product.clone());
+
+				match self.findcrafterindex(&product) {
+					Some(index) => self.upgradecrafter(index, missing),
+					None => {
+						let crafter = RecipeCrafter::with_goal(recipe, facility, &missing);
+						self.connectcrafter(crafter);
+					},
+				}
+
+				progressed = true;
+			}
+
+			if !progressed {
+				panic!("solve() could not make progress resolving factory deficits");
+			}
+		}
+
+		self.balance().map(|_| ())
+	}
+}
+
+
+////////////////////////////////////////////
+// Exact Linear-System Solving for Loops //
+////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+	NoCrafters,
+	Inconsistent,
+}
+
+impl fmt::Display for BalanceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BalanceError::NoCrafters => write!(f, "factory has no crafters to balance"),
+			BalanceError::Inconsistent => {
+				write!(f, "no assignment of crafter quantities satisfies every net rate")
+			},
+		}
+	}
+}
+
+impl std::error::Error for BalanceError {}
+
+impl<'a> Factory<'a> {
+	// Treats every connected crafter's howmany as an unknown and solves
+	// for all of them at once over Rational64, so that recipes forming a
+	// production loop (where with_goal()-then-ratio would never converge)
+	// still come out balanced. One equation per intermediate product (any
+	// product other than the goal and the ignored raw inputs: its summed
+	// net rate across all crafters must be zero) plus one equation
+	// pinning the goal product to the requested rate, solved by rational
+	// Gauss-Jordan elimination -- no floating point anywhere.
+	//
+	// Returns the names of any recipes whose quantity came out underdetermined
+	// (more recipe choices than constraints); those crafters are left at
+	// whatever howmany they already had. An inconsistent system (a loop
+	// that cannot be balanced at all) is reported as Err rather than panicking.
+	pub fn balance(&mut self) -> Result<Vec<String>, BalanceError> {
+		let n = self.crafters.len();
+		if n == 0 {
+			return Err(BalanceError::NoCrafters);
+		}
+
+		let mut intermediateproducts: Vec<&Product> = Vec::new();
+		for crafter in self.crafters.iter() {
+			for quantity in crafter.recipe().outputs().iter().chain(crafter.recipe().inputs()) {
+				let product = quantity.product();
+				let ignored = self.ignoredrates.get(product).copied().unwrap_or(false);
+				if product != self.goal.product() && !ignored && !intermediateproducts.contains(&product) {
+					intermediateproducts.push(product);
+				}
+			}
+		}
+
+		let mut rows: Vec<Vec<Rational64>> = Vec::new();
+
+		for product in intermediateproducts.iter() {
+			let mut row: Vec<Rational64> = self.crafters.iter()
+				.map(|crafter| crafter.unitrate(product).persecond)
+				.collect();
+			row.push(Rational64::zero());
+			rows.push(row);
+		}
+
+		let mut goalrow: Vec<Rational64> = self.crafters.iter()
+			.map(|crafter| crafter.unitrate(self.goal.product()).persecond)
+			.collect();
+		goalrow.push(self.goal.quantity().persecond);
+		rows.push(goalrow);
+
+		let (solution, freecolumns) = gaussianeliminate(rows, n)
+			.ok_or(BalanceError::Inconsistent)?;
+
+		for (i, crafter) in self.crafters.iter_mut().enumerate() {
+			if !freecolumns.contains(&i) {
+				crafter.howmany = solution[i];
+			}
+			crafter.computerates();
+		}
+		self.computerates();
+
+		let freerecipenames = freecolumns.iter()
+			.map(|&i| self.crafters[i].recipe().name().to_owned())
+			.collect();
+		Ok(freerecipenames)
+	}
+}
+
+// Rational Gauss-Jordan elimination on an augmented m x (n+1) matrix (the
+// last column is the right-hand side). Returns None if the system is
+// inconsistent; otherwise returns a particular solution (free columns get
+// the value 0) along with which columns ended up without a pivot at all
+fn gaussianeliminate(mut rows: Vec<Vec<Rational64>>, n: usize) -> Option<(Vec<Rational64>, Vec<usize>)> {
+	let zero = Rational64::zero();
+	let mut pivotrow = 0;
+	let mut pivotcolumnof: Vec<Option<usize>> = vec![None; rows.len()];
+	let mut columnhaspivot = vec![false; n];
+
+	for col in 0..n {
+		if pivotrow >= rows.len() {
+			break;
+		}
+
+		let chosen = (pivotrow..rows.len()).find(|&r| rows[r][col] != zero);
+		let chosen = match chosen {
+			Some(r) => r,
+			None => continue,  // no nonzero entry left in this column -- it's free
+		};
+		rows.swap(pivotrow, chosen);
+
+		let pivotvalue = rows[pivotrow][col];
+		for value in rows[pivotrow].iter_mut() {
+			*value /= pivotvalue;
+		}
+
+		for r in 0..rows.len() {
+			if r != pivotrow && rows[r][col] != zero {
+				let factor = rows[r][col];
+				for c in 0..=n {
+					rows[r][c] -= factor * rows[pivotrow][c];
+				}
+			}
+		}
+
+		pivotcolumnof[pivotrow] = Some(col);
+		columnhaspivot[col] = true;
+		pivotrow += 1;
+	}
+
+	for row in rows.iter() {
+		if row[..n].iter().all(|&v| v == zero) && row[n] != zero {
+			return None;
+		}
+	}
+
+	let mut solution = vec![zero; n];
+	for (r, col) in pivotcolumnof.iter().enumerate() {
+		if let Some(col) = col {
+			solution[*col] = rows[r][n];
+		}
+	}
+
+	let freecolumns: Vec<usize> = (0..n).filter(|&c| !columnhaspivot[c]).collect();
+
+	Some((solution, freecolumns))
+}
+
+#[cfg(test)]
+mod gaussianeliminate_tests {
+	use super::*;
+
+	fn r(n: i64, d: i64) -> Rational64 {
+		Rational64::new(n, d)
+	}
+
+	#[test]
+	fn solves_a_determined_system() {
+		// x + y = 3, x - y = 1 => x = 2, y = 1
+		let rows = vec![
+			vec![r(1, 1), r(1, 1), r(3, 1)],
+			vec![r(1, 1), r(-1, 1), r(1, 1)],
+		];
+
+		let (solution, freecolumns) = gaussianeliminate(rows, 2).unwrap();
+
+		assert_eq!(solution, vec![r(2, 1), r(1, 1)]);
+		assert!(freecolumns.is_empty());
+	}
+
+	#[test]
+	fn reports_free_columns_when_underdetermined() {
+		// One equation, two unknowns: x + y = 2
+		let rows = vec![vec![r(1, 1), r(1, 1), r(2, 1)]];
+
+		let (solution, freecolumns) = gaussianeliminate(rows, 2).unwrap();
+
+		assert_eq!(solution[0], r(2, 1));
+		assert_eq!(freecolumns, vec![1]);
+	}
+
+	#[test]
+	fn reports_inconsistent_systems_as_none() {
+		// 0 = 1 can never be satisfied
+		let rows = vec![vec![r(0, 1), r(0, 1), r(1, 1)]];
+
+		assert_eq!(gaussianeliminate(rows, 2), None);
+	}
+
+	#[test]
+	fn handles_an_empty_system() {
+		let rows: Vec<Vec<Rational64>> = Vec::new();
+
+		let (solution, freecolumns) = gaussianeliminate(rows, 0).unwrap();
+
+		assert!(solution.is_empty());
+		assert!(freecolumns.is_empty());
+	}
+}
+
+#[cfg(test)]
+mod balance_tests {
+	use super::*;
+
+	use crate::facilities::{Facility, FacilityCategory};
+	use crate::recipes::Recipe;
+
+	fn r(n: i64, d: i64) -> Rational64 {
+		Rational64::new(n, d)
+	}
+
+	// A two-recipe loop (each recipe consumes the other's output) is
+	// exactly what balance() exists for -- solve()'s fixpoint can never
+	// settle on one, since satisfying either recipe's deficit always
+	// reopens a deficit in the other. Ratios are picked asymmetrically
+	// (2 output : 1 input one way, 1:1 the other) so the loop has a real
+	// solution instead of the degenerate 1:1-both-ways case, where no
+	// howmany could ever yield positive net output at all
+	#[test]
+	fn balances_a_two_recipe_production_loop() {
+		let category = FacilityCategory::new("assembler");
+		let facility = Facility::new(category.clone(), String::new(), r(1, 1));
+
+		let output = crate::products::Product::new("loop output");
+		let catalyst = crate::products::Product::new("loop catalyst");
+
+		let makeoutput = Recipe::with_name(
+			"make output from catalyst",
+			vec![ProductQuantity::new(r(2, 1), output.clone())],
+			vec![ProductQuantity::new(r(1, 1), catalyst.clone())],
+			crate::rates::Time::new(r(1, 1)),
+			category.clone(),
+		);
+		let makecatalyst = Recipe::with_name(
+			"make catalyst from output",
+			vec![ProductQuantity::new(r(1, 1), catalyst.clone())],
+			vec![ProductQuantity::new(r(1, 1), output.clone())],
+			crate::rates::Time::new(r(1, 1)),
+			category.clone(),
+		);
+
+		let goal = ProductQuantity::new(Rate::new(r(2, 1)), output.clone());
+		let mut factory = Factory::new(goal);
+
+		factory.connectcrafter(RecipeCrafter::new(makeoutput, facility.clone(), r(1, 1)));
+		factory.connectcrafter(RecipeCrafter::new(makecatalyst, facility, r(1, 1)));
+
+		let freerecipes = factory.balance().expect("an asymmetric loop should balance");
+
+		assert!(freerecipes.is_empty());
+		assert_eq!(factory.crafters()[0].howmany(), r(2, 1));
+		assert_eq!(factory.crafters()[1].howmany(), r(2, 1));
+	}
+}
+
+
+//////////////////////////////////////////////
+// Alternate-Recipe Search (see algorithms.rs) //
+//////////////////////////////////////////////
+
+impl<'a> Factory<'a> {
+	// Hard cap on how many solutions get pulled out of the (possibly
+	// infinite, if recipes can loop) lazy search stream before they're
+	// ranked by cost -- a caller after a good-enough answer doesn't need
+	// the search run to exhaustion
+	const SEARCHLIMIT: usize = 64;
+
+	// Enumerates alternate factory configurations for this factory's goal
+	// by trying every candidate recipe and facility tier for each deficit
+	// (see crate::algorithms for the underlying search engine), and
+	// returns up to SEARCHLIMIT of them sorted from cheapest to costliest
+	// according to costfn, e.g. ceil(howmany) summed for facility count
+	pub fn search(
+		self,
+		db: &'a DataBase<'a>,
+		costfn: impl Fn(&Factory<'a>) -> Rational64,
+	) -> Vec<Factory<'a>> {
+		let mut solutions: Vec<Factory<'a>> = crate::algorithms::search(db, self)
+			.take(Self::SEARCHLIMIT)
+			.collect();
+		solutions.sort_by(|a, b| costfn(a).cmp(&costfn(b)));
+		solutions
+	}
 }
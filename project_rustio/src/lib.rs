@@ -13,7 +13,7 @@ mod recipereaders;
 mod dataloaders;
 
 mod factories;
-// mod algorithms;
+mod algorithms;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
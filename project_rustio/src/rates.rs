@@ -1,14 +1,12 @@
 // rates.rs
 // Simple types for representing measurements with dimensions of time or 1/time
 
-// TODO: add functions for parsing times and rates from strings with units
-// TODO: add functions for outputing times and rates with converted units
-
 use std::{convert, fmt, ops::{self, DivAssign}};
 
+use num::Signed;
 use num::rational::Rational64;
 
-use crate::rationalutilities::prettystring;
+use crate::rationalutilities::{prettystring, readrational_prefix};
 
 
 // I want to keep rates and times rational, so they should avoid float math
@@ -30,6 +28,105 @@ impl IntegerOrRational for Rational64 {
 }
 
 
+///////////////////////////////////
+// Units for Parsing and Display //
+///////////////////////////////////
+
+// The units a Time (or the time-dimension of a Rate) may be quoted in
+// Minutes/hours/days are exact rational multiples of seconds, so converting
+// between them never drifts the way float-based unit conversion would
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+	Seconds,
+	Minutes,
+	Hours,
+	Days,
+}
+
+impl TimeUnit {
+	fn secondsperunit(self) -> Rational64 {
+		match self {
+			TimeUnit::Seconds => Rational64::new(1, 1),
+			TimeUnit::Minutes => Rational64::new(60, 1),
+			TimeUnit::Hours => Rational64::new(3600, 1),
+			TimeUnit::Days => Rational64::new(86400, 1),
+		}
+	}
+
+	fn suffix(self) -> &'static str {
+		match self {
+			TimeUnit::Seconds => "s",
+			TimeUnit::Minutes => "min",
+			TimeUnit::Hours => "h",
+			TimeUnit::Days => "d",
+		}
+	}
+
+	fn fromtext(text: &str) -> Option<Self> {
+		match text {
+			"s" | "sec" | "secs" | "second" | "seconds" => Some(TimeUnit::Seconds),
+			"m" | "min" | "mins" | "minute" | "minutes" => Some(TimeUnit::Minutes),
+			"h" | "hr" | "hrs" | "hour" | "hours" => Some(TimeUnit::Hours),
+			"d" | "day" | "days" => Some(TimeUnit::Days),
+			_ => None,
+		}
+	}
+
+	// Pick whichever unit gives the quantity the most readable magnitude,
+	// i.e. the largest unit for which the converted value is still >= 1
+	fn auto(seconds: Rational64) -> Self {
+		let magnitude = seconds.abs();
+		if magnitude >= TimeUnit::Days.secondsperunit() {
+			TimeUnit::Days
+		} else if magnitude >= TimeUnit::Hours.secondsperunit() {
+			TimeUnit::Hours
+		} else if magnitude >= TimeUnit::Minutes.secondsperunit() {
+			TimeUnit::Minutes
+		} else {
+			TimeUnit::Seconds
+		}
+	}
+
+	// Same idea as auto(), but applied to a per-second rate: pick the
+	// smallest per-unit-time denominator for which the converted rate is
+	// still >= 1, since belt throughput usually reads best per minute
+	fn auto_rate(persecond: Rational64) -> Self {
+		let magnitude = persecond.abs();
+		let one = Rational64::new(1, 1);
+		if magnitude * TimeUnit::Seconds.secondsperunit() >= one {
+			TimeUnit::Seconds
+		} else if magnitude * TimeUnit::Minutes.secondsperunit() >= one {
+			TimeUnit::Minutes
+		} else if magnitude * TimeUnit::Hours.secondsperunit() >= one {
+			TimeUnit::Hours
+		} else {
+			TimeUnit::Days
+		}
+	}
+}
+
+// A unit to display a Time/Rate in, with an "auto" option that picks
+// whichever unit reads best for the particular value being displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayUnit {
+	Fixed(TimeUnit),
+	Auto,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitParseError {
+	pub text: String,
+}
+
+impl fmt::Display for UnitParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "unable to parse {:?} as a unit-quantified time or rate", self.text)
+	}
+}
+
+impl std::error::Error for UnitParseError {}
+
+
 //////////////////////////////
 // Time Type Implementation //
 //////////////////////////////
@@ -44,6 +141,24 @@ impl Time {
 		let seconds = seconds.ration();
 		Self { seconds }
 	}
+
+	// Parse a unit-quantified time like "45s", "2 min", or "1.5h"
+	pub fn parse(s: &str) -> Result<Self, UnitParseError> {
+		let trimmed = s.trim();
+		let (quantity, rest) = readrational_prefix(trimmed)
+			.ok_or_else(|| UnitParseError { text: s.to_owned() })?;
+		let unit = TimeUnit::fromtext(rest.trim())
+			.ok_or_else(|| UnitParseError { text: s.to_owned() })?;
+		Ok(Self::new(quantity * unit.secondsperunit()))
+	}
+
+	pub fn display_as(&self, unit: DisplayUnit) -> String {
+		let unit = match unit {
+			DisplayUnit::Fixed(unit) => unit,
+			DisplayUnit::Auto => TimeUnit::auto(self.seconds),
+		};
+		format!("{}{}", prettystring(self.seconds / unit.secondsperunit()), unit.suffix())
+	}
 }
 
 impl fmt::Display for Time {
@@ -171,6 +286,26 @@ impl Rate {
 		let persecond = persecond.ration();
 		Self { persecond }
 	}
+
+	// Parse a unit-quantified rate like "30/min", "90/hour", or "2/m"
+	pub fn parse(s: &str) -> Result<Self, UnitParseError> {
+		let trimmed = s.trim();
+		let (quantity, rest) = readrational_prefix(trimmed)
+			.ok_or_else(|| UnitParseError { text: s.to_owned() })?;
+		let rest = rest.trim().strip_prefix('/')
+			.ok_or_else(|| UnitParseError { text: s.to_owned() })?;
+		let unit = TimeUnit::fromtext(rest.trim())
+			.ok_or_else(|| UnitParseError { text: s.to_owned() })?;
+		Ok(Self::new(quantity / unit.secondsperunit()))
+	}
+
+	pub fn display_as(&self, unit: DisplayUnit) -> String {
+		let unit = match unit {
+			DisplayUnit::Fixed(unit) => unit,
+			DisplayUnit::Auto => TimeUnit::auto_rate(self.persecond),
+		};
+		format!("{}/{}", prettystring(self.persecond * unit.secondsperunit()), unit.suffix())
+	}
 }
 
 impl fmt::Display for Rate {
@@ -1,9 +1,11 @@
 // rationalutilities.rs
 // Utilities for picky methods of input and output involving rationals
 
-use lazy_static::lazy_static;
-use num::{FromPrimitive, rational::Rational64};
-use regex::Regex;
+use std::error;
+use std::fmt;
+
+use num::Signed;
+use num::rational::Rational64;
 
 
 ///////////////////////////////
@@ -24,50 +26,266 @@ pub fn prettystring(r: Rational64) -> String {
 	}
 }
 
+// Exact mixed-number rendering, e.g. 7/6 -> "1 1/6", -3/2 -> "-1 1/2"
+// Integers are printed plain, same as prettystring()
+pub fn prettystring_mixed(r: Rational64) -> String {
+	if r.is_integer() {
+		return format!("{}", r.numer());
+	}
+
+	let sign = if r.is_negative() { "-" } else { "" };
+	let r = r.abs();
+	let whole = r.to_integer();
+	let fract = r.fract();
+
+	format!("{}{} {}/{}", sign, whole, fract.numer(), fract.denom())
+}
+
+// Exact best-approximation rendering with a bounded denominator, in the
+// spirit of ffmpeg's reduce_with_limit(): run the continued-fraction
+// expansion of r, keeping convergent recurrences h_i = a_i*h_{i-1} + h_{i-2}
+// and k_i = a_i*k_{i-1} + k_{i-2} (seeded h_{-1}=1, h_{-2}=0, k_{-1}=0,
+// k_{-2}=1), until the denominator k_i would exceed maxdenominator. At that
+// point form the semiconvergent a' = floor((Q - k_{i-2}) / k_{i-1}) and
+// return whichever of it or the previous convergent is closer to r
+// A maxdenominator below 1 is a degenerate bound (nothing with denominator
+// >= 1 can satisfy it), so it's reported as None rather than handed to
+// boundeddenominator(), which would divide by a zero k_prev1 on its first
+// semiconvergent step
+pub fn prettystring_bounded(r: Rational64, maxdenominator: i64) -> Option<String> {
+	if maxdenominator < 1 {
+		return None;
+	}
+
+	if r.is_integer() {
+		return Some(format!("{}", r.numer()));
+	}
+
+	let sign = if r.is_negative() { -1 } else { 1 };
+	let approximation = boundeddenominator(r.abs(), maxdenominator);
+	let approximation = Rational64::new(sign, 1) * approximation;
+
+	Some(if approximation.is_integer() {
+		format!("{}", approximation.numer())
+	} else {
+		format!("{}/{}", approximation.numer(), approximation.denom())
+	})
+}
+
+// Continued-fraction convergent search for the closest fraction to a
+// positive, non-integer rational whose denominator does not exceed Q
+fn boundeddenominator(x: Rational64, maxdenominator: i64) -> Rational64 {
+	let (mut num, mut den) = (*x.numer(), *x.denom());
+
+	let (mut h_prev2, mut h_prev1) = (0i64, 1i64);
+	let (mut k_prev2, mut k_prev1) = (1i64, 0i64);
+
+	loop {
+		let a = num / den;
+		let h = a * h_prev1 + h_prev2;
+		let k = a * k_prev1 + k_prev2;
+
+		if k > maxdenominator {
+			let semi_a = (maxdenominator - k_prev2) / k_prev1;
+			let semiconvergent = Rational64::new(
+				semi_a * h_prev1 + h_prev2,
+				semi_a * k_prev1 + k_prev2
+			);
+			let convergent = Rational64::new(h_prev1, k_prev1);
+
+			return if (semiconvergent - x).abs() <= (convergent - x).abs() {
+				semiconvergent
+			} else {
+				convergent
+			};
+		}
+
+		let remainder = num - a * den;
+		if remainder == 0 {
+			return Rational64::new(h, k);
+		}
+
+		num = den;
+		den = remainder;
+		h_prev2 = h_prev1;
+		h_prev1 = h;
+		k_prev2 = k_prev1;
+		k_prev1 = k;
+	}
+}
+
 
 //////////////////////////////
 // Rational Input Utilities //
 //////////////////////////////
 
-lazy_static! {
-	static ref RATIONALREGEX: Regex = Regex::new(
-	    r"^ *([-+]?) *([0-9]+) */{1,2} *([0-9]+) *$"
-    ).unwrap();
-}
-
-// This implementation with all the trial-and-error branching is a bit hacky
-// It ought to work okay -- a better implementation would involve more regexes
-// TODO: implement a better version of this function with all the picky details
-fn tryreadrational(s: &str) -> Option<Rational64> {
-	
-	let m = RATIONALREGEX.captures(s);
-	if let Some(m) = m {
-		let numer = m.get(2).unwrap().as_str().parse().unwrap();
-		let denom = m.get(3).unwrap().as_str().parse().unwrap();
-		let r = Rational64::new(numer, denom);
-		match m.get(1).unwrap().as_str() {
-			"-" => return Some(-r),
-			_ => return Some(r),
-		}
+// readrational() is built as a small pipeline of parser combinators instead
+// of one big regex, in the spirit of a parser-combinator crate like chumsky.
+// Each combinator below takes the remaining unparsed text and either returns
+// the value it recognized plus whatever text is left, or fails outright so
+// the next alternative in the pipeline can have a try. The grammar handled:
+//     rational := sign? (percentage | mixed | fraction | decimal)
+//    percentage := (mixed | fraction | decimal) "%"
+//         mixed := integer whitespace fraction
+//      fraction := integer ("/" | "//") integer
+//       decimal := digits? ("." digits?)? (("e" | "E") sign? digits)?
+// Decimals and scientific notation are converted to an exact Rational64 as
+// numer/10^k -- never through from_f64(), so no binary-float drift creeps in.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RationalParseError {
+	pub position: usize,
+	pub text: String,
+}
+
+impl fmt::Display for RationalParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"unable to parse {:?} as a rational at position {}",
+			self.text, self.position
+		)
+	}
+}
+
+impl error::Error for RationalParseError {}
+
+fn parse_ws(s: &str) -> &str {
+	s.trim_start_matches(' ')
+}
+
+fn parse_sign(s: &str) -> (i64, &str) {
+	let s = parse_ws(s);
+	match s.strip_prefix('-') {
+		Some(rest) => (-1, rest),
+		None => match s.strip_prefix('+') {
+			Some(rest) => (1, rest),
+			None => (1, s),
+		},
+	}
+}
+
+fn parse_digits(s: &str) -> Option<(&str, &str)> {
+	let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	if end == 0 {
+		None
+	} else {
+		Some((&s[..end], &s[end..]))
+	}
+}
+
+fn parse_integer(s: &str) -> Option<(i64, &str)> {
+	let (digits, rest) = parse_digits(s)?;
+	Some((digits.parse().ok()?, rest))
+}
+
+// Decimal and scientific-notation literals, e.g. "3", "3.14", "2.5e-3", ".5"
+fn parse_decimal(s: &str) -> Option<(Rational64, &str)> {
+	let (intdigits, rest) = parse_digits(s).unwrap_or(("", s));
+
+	let (fracdigits, rest) = match rest.strip_prefix('.') {
+		Some(afterdot) => {
+			let (digits, rest) = parse_digits(afterdot).unwrap_or(("", afterdot));
+			(digits, rest)
+		},
+		None => ("", rest),
+	};
+
+	if intdigits.is_empty() && fracdigits.is_empty() {
+		return None;
 	}
-	
-	let i: Option<i64> = s.parse().ok();
-	if let Some(i) = i {
-		return Some(Rational64::new(i, 1));
+
+	let mantissa: i64 = format!("{}{}", intdigits, fracdigits).parse().ok()?;
+	let fraclen = fracdigits.len() as i32;
+
+	let (exponent, rest) = match rest.strip_prefix(['e', 'E']) {
+		Some(afterexp) => {
+			let (expsign, afterexpsign) = parse_sign(afterexp);
+			let (expdigits, rest) = parse_integer(afterexpsign)?;
+			(expsign * expdigits, rest)
+		},
+		None => (0, rest),
+	};
+
+	// checked_pow/checked_mul instead of the panicking operators: a shift
+	// this large (e.g. "1e30") would overflow i64 outright, and readrational()
+	// is supposed to report that as a parse error rather than crash
+	// exponent is only converted to i32 via a checked cast -- an `as i32`
+	// here would silently wrap an exponent like 1e4294967296 down to 0
+	// instead of rejecting it
+	let exponent: i32 = exponent.try_into().ok()?;
+	let shift = exponent - fraclen;
+	let value = if shift >= 0 {
+		let scale = 10i64.checked_pow(shift as u32)?;
+		Rational64::new(mantissa.checked_mul(scale)?, 1)
+	} else {
+		let scale = 10i64.checked_pow(shift.unsigned_abs())?;
+		Rational64::new(mantissa, scale)
+	};
+
+	Some((value, rest))
+}
+
+// A plain fraction, e.g. "3/4" or "3 // 4" (one or two slashes, as before)
+fn parse_fraction(s: &str) -> Option<(Rational64, &str)> {
+	let (numer, rest) = parse_integer(s)?;
+	let rest = parse_ws(rest);
+	let rest = rest.strip_prefix("//").or_else(|| rest.strip_prefix('/'))?;
+	let rest = parse_ws(rest);
+	let (denom, rest) = parse_integer(rest)?;
+	if denom == 0 {
+		return None;
 	}
-	
-	let f: Option<f64> = s.parse().ok();
-	if let Some(f) = f {
-		return Some(Rational64::from_f64(f).unwrap());
+	Some((Rational64::new(numer, denom), rest))
+}
+
+// A mixed number, e.g. "1 1/2" -- a whole part, required whitespace, a fraction
+fn parse_mixed(s: &str) -> Option<(Rational64, &str)> {
+	let (whole, rest) = parse_integer(s)?;
+	if !rest.starts_with(' ') {
+		return None;
 	}
-	
-	None
+	let (fractional, rest) = parse_fraction(parse_ws(rest))?;
+	Some((Rational64::new(whole, 1) + fractional, rest))
+}
+
+fn parse_number(s: &str) -> Option<(Rational64, &str)> {
+	parse_mixed(s)
+		.or_else(|| parse_fraction(s))
+		.or_else(|| parse_decimal(s))
+}
+
+// A number followed by a trailing "%", e.g. "50%" or "33 1/3%"
+fn parse_percentage(s: &str) -> Option<(Rational64, &str)> {
+	let (value, rest) = parse_number(s)?;
+	let rest = parse_ws(rest).strip_prefix('%')?;
+	Some((value / 100, rest))
 }
 
-pub fn readrational(s: &str) -> Rational64 {
-	let r = tryreadrational(s);
-	if let None = r {
-		panic!("string {:?} could not be read as a rational", s);
+pub fn readrational(s: &str) -> Result<Rational64, RationalParseError> {
+	let (sign, aftersign) = parse_sign(s);
+	let numberstart = s.len() - aftersign.len();
+
+	let parsed = parse_percentage(aftersign).or_else(|| parse_number(aftersign));
+	let (value, leftover) = match parsed {
+		Some(result) => result,
+		None => return Err(RationalParseError { position: numberstart, text: s.to_owned() }),
+	};
+
+	let leftover = parse_ws(leftover);
+	if !leftover.is_empty() {
+		let position = s.len() - leftover.len();
+		return Err(RationalParseError { position, text: s.to_owned() });
 	}
-	r.unwrap()
+
+	Ok(Rational64::new(sign, 1) * value)
+}
+
+// Like readrational(), but does not require the whole string to be consumed
+// This lets callers with trailing non-numeric content (e.g. a unit suffix)
+// reuse the same number grammar without having to reimplement it themselves
+pub(crate) fn readrational_prefix(s: &str) -> Option<(Rational64, &str)> {
+	let (sign, aftersign) = parse_sign(s);
+	let (value, leftover) = parse_percentage(aftersign).or_else(|| parse_number(aftersign))?;
+	Some((Rational64::new(sign, 1) * value, leftover))
 }
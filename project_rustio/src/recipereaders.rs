@@ -1,11 +1,14 @@
 // recipereaders.rs
 // Code for parsing recipes from the recipe data file into Julia types
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use lazy_static::lazy_static;
+use num::rational::Rational64;
 use regex::Regex;
 
 use crate::abbreviations::AbbreviationResolver;
@@ -17,33 +20,499 @@ use crate::rationalutilities::readrational;
 use crate::recipes::Recipe;
 
 
+///////////////////////////
+// RecipeParseError Type //
+///////////////////////////
+
+// Everything that can go wrong while reading the recipe data file, each
+// carrying the 1-based line number (within the whole file, not just the
+// block it fell in) and the offending text where one is available, so a
+// single typo reports its exact location instead of aborting the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipeParseError {
+	IoError { file: PathBuf, message: String },
+	EmptyRecipeBlock { line: usize },
+	UnparsableArrowLine { line: usize, text: String },
+	MissingArrowLine { line: usize },
+	UnparsableQuantity { line: usize, text: String },
+	ExpectedProductName { line: usize },
+	UnparsableNametag { line: usize, text: String },
+	NametagBeforeEnd { line: usize, text: String },
+	UnknownProduct { line: usize, text: String },
+	UnknownFacilityCategory { line: usize, text: String },
+	UnterminatedVariable { line: usize, text: String },
+	DuplicateTemplate { line: usize, name: String },
+	UnknownTemplate { line: usize, name: String },
+	UnboundTemplateVariable { line: usize, variable: String },
+	MissingInclude { file: PathBuf, line: usize, included: PathBuf, message: String },
+	CyclicInclude { file: PathBuf, line: usize, included: PathBuf },
+}
+
+impl fmt::Display for RecipeParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RecipeParseError::IoError { file, message } => {
+				write!(f, "unable to read {:?}: {}", file, message)
+			},
+			RecipeParseError::EmptyRecipeBlock { line } => {
+				write!(f, "line {}: recipe block is empty", line)
+			},
+			RecipeParseError::UnparsableArrowLine { line, text } => {
+				write!(f, "line {}: unable to parse arrow line: {:?}", line, text)
+			},
+			RecipeParseError::MissingArrowLine { line } => {
+				write!(f, "line {}: recipe block is missing its arrow line", line)
+			},
+			RecipeParseError::UnparsableQuantity { line, text } => {
+				write!(f, "line {}: unable to parse ingredient quantity: {:?}", line, text)
+			},
+			RecipeParseError::ExpectedProductName { line } => {
+				write!(f, "line {}: expected a product name after the quantity", line)
+			},
+			RecipeParseError::UnparsableNametag { line, text } => {
+				write!(f, "line {}: unable to parse nametag line: {:?}", line, text)
+			},
+			RecipeParseError::NametagBeforeEnd { line, text } => {
+				write!(f, "line {}: found {:?} before the end of the recipe block", line, text)
+			},
+			RecipeParseError::UnknownProduct { line, text } => {
+				write!(f, "line {}: unknown product: {:?}", line, text)
+			},
+			RecipeParseError::UnknownFacilityCategory { line, text } => {
+				write!(f, "line {}: unknown facility category: {:?}", line, text)
+			},
+			RecipeParseError::UnterminatedVariable { line, text } => {
+				write!(f, "line {}: unterminated {{{{variable}}}} in {:?}", line, text)
+			},
+			RecipeParseError::DuplicateTemplate { line, name } => {
+				write!(f, "line {}: duplicate template name: {:?}", line, name)
+			},
+			RecipeParseError::UnknownTemplate { line, name } => {
+				write!(f, "line {}: @use references unknown template: {:?}", line, name)
+			},
+			RecipeParseError::UnboundTemplateVariable { line, variable } => {
+				write!(f, "line {}: @use is missing a binding for {:?}", line, variable)
+			},
+			RecipeParseError::MissingInclude { file, line, included, message } => {
+				write!(f, "{:?} line {}: cannot include {:?}: {}", file, line, included, message)
+			},
+			RecipeParseError::CyclicInclude { file, line, included } => {
+				write!(f, "{:?} line {}: cyclic include of {:?}", file, line, included)
+			},
+		}
+	}
+}
+
+impl error::Error for RecipeParseError {}
+
+
 //////////////////////////////////////////////
 // Recipe Block Specification for Data File //
 //////////////////////////////////////////////
 
 // Any whitespace at the start or end of a line is stripped before parsing
 // Recipe blocks are separated by one or more blank lines in the data file
-// Any line beginning with a '#' is ignored (and not considered to be blank)
+// A line beginning with a '#' (after trimming) is a whole-line comment, and
+// a trailing "#..." tail on any other line is an inline comment -- neither
+// ever counts as blank
 
-// A BNF-style description of the recipe block grammar:
+// A BNF-style description of the recipe block grammar, now read off a token
+// stream (see the Token/TokenKind types below) rather than matched line by
+// line with regexes:
 //            recipe_block := outputs arrow_line inputs optional_nametag_line
 //                 outputs := ingredient_line | ingredient_line outputs
-//              arrow_line := "^" time_period "s" "(" facility_category ")"
+//              arrow_line := Arrow Number Seconds LParen Ident RParen
 //                  inputs := ingredient_line | ingredient_line inputs
-//   optional_nametag_line := "" | "<>" recipe_name
-//         ingredient_line := number product_name
+//   optional_nametag_line := "" | NameTag Ident
+//         ingredient_line := Number Ident
+//                  Number := integer | decimal | "p/q" fraction
 
 lazy_static! {
-	static ref COMMENTLINEREGEX: Regex = Regex::new(r"^\#.*$").unwrap();
-	static ref ARROWLINEREGEX: Regex = Regex::new(r"^\^ *([0-9.]+) *s *\((.+)\)$").unwrap();
-	static ref NAMETAGLINEREGEX: Regex = Regex::new(r"^<> *(.+)$").unwrap();
-	static ref INGREDIENTLINEREGEX: Regex = Regex::new(r"^([0-9]+) +(.+)$").unwrap();
+	static ref TEMPLATEHEADERREGEX: Regex = Regex::new(r"^@template +([A-Za-z_][A-Za-z0-9_]*) *\((.*)\)$").unwrap();
+	static ref USEHEADERREGEX: Regex = Regex::new(r"^@use +([A-Za-z_][A-Za-z0-9_]*) *\((.*)\)$").unwrap();
+	static ref INCLUDEDIRECTIVEREGEX: Regex = Regex::new(r"^\#include +(.+)$").unwrap();
+}
+
+
+//////////////////////////////////
+// #include Directive Expansion //
+//////////////////////////////////
+
+// Reads a single data file into its trimmed, 1-based-numbered lines,
+// recursively splicing in the lines of any "#include path" directive at the
+// position it appears. Every other line, comments included, passes through
+// unchanged -- the lexer is what turns a plain "#" comment into a token
+// later on. Include paths are resolved relative to the directory of the
+// file that names them, and includestack (the set of canonicalized paths
+// currently being read, from the top-level file down to this one) is used
+// to reject a cycle instead of recursing forever
+fn readlines_expandingincludes(
+	path: &Path,
+	includestack: &mut HashSet<PathBuf>,
+) -> Result<Vec<(usize, String)>, RecipeParseError> {
+
+	let contents = fs::read_to_string(path).map_err(|e| RecipeParseError::IoError {
+		file: path.to_path_buf(),
+		message: e.to_string(),
+	})?;
+
+	let basedir = path.parent().unwrap_or_else(|| Path::new(""));
+	let mut expanded = Vec::new();
+
+	for (i, rawline) in contents.lines().enumerate() {
+		let linenum = i + 1;
+		let line = rawline.trim();
+
+		if let Some(m) = INCLUDEDIRECTIVEREGEX.captures(line) {
+			let (_, [includedpath]) = m.extract();
+			let includedpath = basedir.join(includedpath);
+
+			let canonical = includedpath.canonicalize().map_err(|e| RecipeParseError::MissingInclude {
+				file: path.to_path_buf(),
+				line: linenum,
+				included: includedpath.clone(),
+				message: e.to_string(),
+			})?;
+
+			if !includestack.insert(canonical.clone()) {
+				return Err(RecipeParseError::CyclicInclude {
+					file: path.to_path_buf(),
+					line: linenum,
+					included: includedpath,
+				});
+			}
+
+			expanded.extend(readlines_expandingincludes(&includedpath, includestack)?);
+			includestack.remove(&canonical);
+			continue;
+		}
+
+		expanded.push((linenum, line.to_owned()));
+	}
+
+	Ok(expanded)
+}
+
+
+///////////////////////////////////////////////////
+// Recipe Templates: {{variable}} Interpolation //
+///////////////////////////////////////////////////
+
+// One segment of a template line: either literal text to copy verbatim, or
+// a {{variable}} placeholder to replace with whatever text it's bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Fragment {
+	Text(String),
+	Variable(String),
+}
+
+// Scans a line for {{ name }} spans (trimming the whitespace inside the
+// braces), splitting it into alternating Text and Variable fragments. A
+// '{{' with no matching '}}' before the end of the line is an error
+fn parsefragments(text: &str, linenum: usize) -> Result<Vec<Fragment>, RecipeParseError> {
+	let mut fragments = Vec::new();
+	let mut rest = text;
+
+	loop {
+		match rest.find("{{") {
+			None => {
+				if !rest.is_empty() {
+					fragments.push(Fragment::Text(rest.to_owned()));
+				}
+				break;
+			},
+			Some(start) => {
+				if start > 0 {
+					fragments.push(Fragment::Text(rest[..start].to_owned()));
+				}
+
+				let afteropen = &rest[start + 2..];
+				match afteropen.find("}}") {
+					None => return Err(RecipeParseError::UnterminatedVariable {
+						line: linenum,
+						text: text.to_owned(),
+					}),
+					Some(end) => {
+						let variablename = afteropen[..end].trim().to_owned();
+						fragments.push(Fragment::Variable(variablename));
+						rest = &afteropen[end + 2..];
+					},
+				}
+			},
+		}
+	}
+
+	Ok(fragments)
+}
+
+// A reusable recipe template declared by a "@template name(params...)"
+// header line, whose body lines are instantiated by "@use" lines elsewhere
+// in the file to generate a family of concrete recipe blocks
+struct RecipeTemplate {
+	params: Vec<String>,
+	body: Vec<Vec<Fragment>>,
+}
+
+fn parsetemplateheader(line: &str) -> Option<(String, Vec<String>)> {
+	let m = TEMPLATEHEADERREGEX.captures(line)?;
+	let (_, [name, paramlist]) = m.extract();
+
+	let params: Vec<String> = paramlist.split(',')
+	    .map(|p| p.trim().to_owned())
+		.filter(|p| !p.is_empty())
+		.collect();
+
+	Some((name.to_owned(), params))
+}
+
+fn parseuseheader(line: &str) -> Option<(String, Vec<(String, String)>)> {
+	let m = USEHEADERREGEX.captures(line)?;
+	let (_, [name, bindinglist]) = m.extract();
+
+	let bindings: Vec<(String, String)> = bindinglist.split(',')
+	    .filter_map(|binding| binding.split_once('='))
+		.map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+		.collect();
+
+	Some((name.to_owned(), bindings))
+}
+
+// Substitutes bindings into every line of a template's body, re-feeding the
+// results through the same ingredient/arrow-line text that
+// readrecipefile() would otherwise have read straight out of the file --
+// so abbreviation resolution and number parsing still apply downstream
+fn instantiatetemplate(
+	template: &RecipeTemplate,
+	bindings: &[(String, String)],
+	uselinenum: usize,
+) -> Result<Vec<(usize, String)>, RecipeParseError> {
+
+	for param in &template.params {
+		if !bindings.iter().any(|(key, _)| key == param) {
+			return Err(RecipeParseError::UnboundTemplateVariable {
+				line: uselinenum,
+				variable: param.clone(),
+			});
+		}
+	}
+
+	let mut lines = Vec::with_capacity(template.body.len());
+	for fragments in &template.body {
+		let mut line = String::new();
+		for fragment in fragments {
+			match fragment {
+				Fragment::Text(text) => line.push_str(text),
+				Fragment::Variable(name) => {
+					let value = bindings.iter().find(|(key, _)| key == name)
+					    .map(|(_, value)| value.as_str())
+						.ok_or_else(|| RecipeParseError::UnboundTemplateVariable {
+							line: uselinenum,
+							variable: name.clone(),
+						})?;
+					line.push_str(value);
+				},
+			}
+		}
+		lines.push((uselinenum, line));
+	}
+
+	Ok(lines)
+}
+
+////////////////////////////////
+// Recipe Block Lexer & Tokens //
+////////////////////////////////
+
+// A lexed piece of a recipe block, tagged with the 1-based file-absolute
+// line it came from so later stages can keep reporting precise locations
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+	Number(Rational64),
+	Ident(String),
+	Arrow,
+	Seconds,
+	LParen,
+	RParen,
+	NameTag,
+	BlankLine,
+	Comment,
+	Eof,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+	pub(crate) kind: TokenKind,
+	pub(crate) line: usize,
+}
+
+// Lexes a single already-trimmed line, pushing zero or more tokens onto
+// tokens. A blank line becomes a lone BlankLine token, a whole-line comment
+// becomes a lone Comment token, and everything else has its trailing
+// "#..." tail (if any) stripped before being split into Number/Ident/Arrow/
+// Seconds/LParen/RParen/NameTag tokens
+fn lexline(linenum: usize, rawline: &str, tokens: &mut Vec<Token>) -> Result<(), RecipeParseError> {
+	let trimmed = rawline.trim();
+
+	if trimmed.is_empty() {
+		tokens.push(Token { kind: TokenKind::BlankLine, line: linenum });
+		return Ok(());
+	}
+
+	if trimmed.starts_with('#') {
+		tokens.push(Token { kind: TokenKind::Comment, line: linenum });
+		return Ok(());
+	}
+
+	let code = match trimmed.find('#') {
+		Some(pos) => trimmed[..pos].trim_end(),
+		None => trimmed,
+	};
+
+	let mut rest = code;
+	while !rest.is_empty() {
+		rest = rest.trim_start();
+		if rest.is_empty() {
+			break;
+		}
+
+		if let Some(stripped) = rest.strip_prefix("<>") {
+			tokens.push(Token { kind: TokenKind::NameTag, line: linenum });
+			rest = stripped;
+			continue;
+		}
+		if let Some(stripped) = rest.strip_prefix('^') {
+			tokens.push(Token { kind: TokenKind::Arrow, line: linenum });
+			rest = stripped;
+			continue;
+		}
+		if let Some(stripped) = rest.strip_prefix('(') {
+			tokens.push(Token { kind: TokenKind::LParen, line: linenum });
+			rest = stripped;
+			continue;
+		}
+		if let Some(stripped) = rest.strip_prefix(')') {
+			tokens.push(Token { kind: TokenKind::RParen, line: linenum });
+			rest = stripped;
+			continue;
+		}
+
+		let wordend = rest.find(|c: char| c.is_whitespace() || "<^()".contains(c))
+			.unwrap_or(rest.len());
+		let word = &rest[..wordend];
+
+		if word == "s" {
+			tokens.push(Token { kind: TokenKind::Seconds, line: linenum });
+		} else if word.as_bytes()[0].is_ascii_digit() {
+			// A seconds count written flush against its unit ("2s") lexes
+			// as a Number followed by a Seconds token
+			let (numbertext, flushseconds) = match word.strip_suffix('s') {
+				Some(head) if !head.is_empty() => (head, true),
+				_ => (word, false),
+			};
+			let quantity = readrational(numbertext).map_err(|_| RecipeParseError::UnparsableQuantity {
+				line: linenum,
+				text: trimmed.to_owned(),
+			})?;
+			tokens.push(Token { kind: TokenKind::Number(quantity), line: linenum });
+			if flushseconds {
+				tokens.push(Token { kind: TokenKind::Seconds, line: linenum });
+			}
+		} else {
+			tokens.push(Token { kind: TokenKind::Ident(word.to_owned()), line: linenum });
+		}
+
+		rest = &rest[wordend..];
+	}
+
+	Ok(())
+}
+
+// Lexes every line of a recipe block in order, ending the stream with a
+// single Eof token carrying the block's last line number
+fn lex(lines: &[(usize, String)]) -> Result<Vec<Token>, RecipeParseError> {
+	let mut tokens = Vec::new();
+	for (linenum, line) in lines {
+		lexline(*linenum, line, &mut tokens)?;
+	}
+
+	let lastline = lines.last().map_or(0, |&(n, _)| n);
+	tokens.push(Token { kind: TokenKind::Eof, line: lastline });
+	Ok(tokens)
 }
 
+// A cursor over a lexed token stream that transparently skips Comment
+// tokens and treats the trailing Eof sentinel as the end of the stream, so
+// the recursive-descent grammar functions below never have to think about
+// either of them
+struct TokenCursor<'a> {
+	tokens: &'a [Token],
+	pos: usize,
+}
+
+impl<'a> TokenCursor<'a> {
+	fn new(tokens: &'a [Token]) -> Self {
+		let mut cursor = Self { tokens, pos: 0 };
+		cursor.skipcomments();
+		cursor
+	}
+
+	fn skipcomments(&mut self) {
+		while matches!(self.tokens.get(self.pos).map(|t| &t.kind), Some(TokenKind::Comment)) {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		match self.tokens.get(self.pos) {
+			Some(token) if !matches!(token.kind, TokenKind::Eof) => Some(token),
+			_ => None,
+		}
+	}
+
+	fn advance(&mut self) {
+		if self.peek().is_some() {
+			self.pos += 1;
+			self.skipcomments();
+		}
+	}
+
+	// The line a caller should blame for "expected more input here" errors:
+	// the next real token if there is one, otherwise the trailing Eof's line
+	fn currentline(&self) -> usize {
+		self.tokens.get(self.pos).map_or(0, |t| t.line)
+	}
+}
+
+// Consumes the next token if matches_kind accepts its TokenKind, returning
+// the kind and line it came from; otherwise builds an error from onmismatch
+// using either the offending token's line/kind or (at the end of the
+// stream) startline and a placeholder description
+fn expecttoken(
+	cursor: &mut TokenCursor,
+	startline: usize,
+	matches_kind: impl Fn(&TokenKind) -> bool,
+	onmismatch: impl Fn(usize, String) -> RecipeParseError,
+) -> Result<(TokenKind, usize), RecipeParseError> {
+	match cursor.peek().map(|t| (t.kind.clone(), t.line)) {
+		Some((kind, line)) if matches_kind(&kind) => {
+			cursor.advance();
+			Ok((kind, line))
+		},
+		Some((kind, line)) => Err(onmismatch(line, format!("{:?}", kind))),
+		None => Err(onmismatch(startline, "end of recipe block".to_owned())),
+	}
+}
+
+
 //////////////////////////////////////////////////
 // Functions for Parsing the Entire Recipe File //
 //////////////////////////////////////////////////
 
+// A recipe block is a run of consecutive, non-blank, file-absolute lines --
+// each line keeps its original 1-based line number so that a parse error
+// discovered later, deep inside a single recipe block, can still report
+// exactly where in the file it came from
+
 // This operates something like split(listoflines, isempty, keepempty = false)
 // However, Base.split() only accepts sequences <: AbstractString as its input
 // There really could exist a somewhat more general version of that function
@@ -52,77 +521,158 @@ lazy_static! {
 // It runs into all kinds of problems with AbstractChar and the SubString type
 // This array-splitting problem also seems to be weirdly, totally un-Google-able
 // Am I the only person who has ever wanted to split up an array like this???
-fn splitonblanklines(listoflines: &Vec<String>) -> Vec<&[String]> {
+fn splitonblanklines(listoflines: Vec<(usize, String)>) -> Vec<Vec<(usize, String)>> {
 	let mut listofblocks = Vec::new();
-	let mut currentblockstart = None;
-	
-	for (i, line) in listoflines.iter().enumerate() {
+	let mut currentblock: Vec<(usize, String)> = Vec::new();
+
+	for entry in listoflines {
+		let (_, line) = &entry;
 		if line.is_empty() {
-			if let Some(start) = currentblockstart {
-				let block = &listoflines[start..i];
-				listofblocks.push(block);
+			if !currentblock.is_empty() {
+				listofblocks.push(std::mem::take(&mut currentblock));
 			}
-			currentblockstart = None;
-		} else if currentblockstart.is_none() {
-			currentblockstart = Some(i);
+		} else {
+			currentblock.push(entry);
 		}
 	}
-	
-	if let Some(start) = currentblockstart {
-		let block = &listoflines[start..];
-		listofblocks.push(block);
+
+	if !currentblock.is_empty() {
+		listofblocks.push(currentblock);
 	}
-	
+
 	listofblocks
 }
 
-pub fn readrecipefile(datafilename: PathBuf, db: &DataBase) -> Vec<Vec<String>> {
+// Registers every "@template name(params)" block into templates (erroring
+// on a duplicate name), expands every "@use name(bindings)" block against
+// the template it names (erroring if no such template was registered), and
+// passes every other block through untouched. The @use line's own line
+// number is used for every line an expansion generates, since those lines
+// don't otherwise exist in the file
+fn expandtemplates(
+	rawblocks: Vec<Vec<(usize, String)>>,
+) -> Result<Vec<Vec<(usize, String)>>, RecipeParseError> {
+
+	let mut templates: HashMap<String, RecipeTemplate> = HashMap::new();
+	let mut expandedblocks = Vec::new();
+
+	for block in rawblocks {
+		let (headerlinenum, headerline) = &block[0];
+		let headerlinenum = *headerlinenum;
+
+		if let Some((name, params)) = parsetemplateheader(headerline) {
+			if templates.contains_key(&name) {
+				return Err(RecipeParseError::DuplicateTemplate { line: headerlinenum, name });
+			}
+
+			let mut body = Vec::with_capacity(block.len() - 1);
+			for (linenum, line) in &block[1..] {
+				body.push(parsefragments(line, *linenum)?);
+			}
+
+			templates.insert(name, RecipeTemplate { params, body });
+			continue;
+		}
+
+		if let Some((name, bindings)) = parseuseheader(headerline) {
+			let template = templates.get(&name).ok_or_else(|| RecipeParseError::UnknownTemplate {
+				line: headerlinenum,
+				name: name.clone(),
+			})?;
+
+			expandedblocks.push(instantiatetemplate(template, &bindings, headerlinenum)?);
+			continue;
+		}
+
+		expandedblocks.push(block);
+	}
+
+	Ok(expandedblocks)
+}
+
+// Resolves every Ident token that stands where a product name or facility
+// category name is expected (immediately after a Number, or immediately
+// after a LParen) against the database's full names, replacing it in place
+// with the name it abbreviates. An Ident that already spells out a full
+// name is left untouched, matching call_fallible()'s own caching behavior
+// This calls call_fallible() once per matching token, so it depends on
+// call_fallible() taking an ordinary &mut self rather than a self-lifetime-
+// tied &'a mut self -- otherwise only the first token in a block could ever
+// be resolved
+fn resolveabbreviations(
+	tokens: &mut [Token],
+	productresolver: &mut AbbreviationResolver,
+	categoryresolver: &mut AbbreviationResolver,
+	allproducts: &HashSet<&str>,
+	allcategories: &HashSet<&str>,
+) -> Result<(), RecipeParseError> {
+
+	for i in 0..tokens.len() {
+		let precededbynumber = matches!(tokens.get(i.wrapping_sub(1)).map(|t| &t.kind), Some(TokenKind::Number(_)));
+		let precededbylparen = matches!(tokens.get(i.wrapping_sub(1)).map(|t| &t.kind), Some(TokenKind::LParen));
+
+		if i == 0 || !(precededbynumber || precededbylparen) {
+			continue;
+		}
+
+		let linenum = tokens[i].line;
+		let name = match &tokens[i].kind {
+			TokenKind::Ident(name) => name.clone(),
+			_ => continue,
+		};
+
+		if precededbynumber && !allproducts.contains(name.as_str()) {
+			let resolved = productresolver.call_fallible(&name)
+				.ok_or_else(|| RecipeParseError::UnknownProduct { line: linenum, text: name.clone() })?
+				.to_owned();
+			tokens[i].kind = TokenKind::Ident(resolved);
+		} else if precededbylparen && !allcategories.contains(name.as_str()) {
+			let resolved = categoryresolver.call_fallible(&name)
+				.ok_or_else(|| RecipeParseError::UnknownFacilityCategory { line: linenum, text: name.clone() })?
+				.to_owned();
+			tokens[i].kind = TokenKind::Ident(resolved);
+		}
+	}
+
+	Ok(())
+}
+
+pub fn readrecipefile(
+	datafilename: PathBuf,
+	db: &DataBase
+) -> Result<Vec<Vec<Token>>, RecipeParseError> {
+
+	let rootcanonical = datafilename.canonicalize().map_err(|e| RecipeParseError::IoError {
+		file: datafilename.clone(),
+		message: e.to_string(),
+	})?;
+	let mut includestack = HashSet::new();
+	includestack.insert(rootcanonical);
+
+	let filelines = readlines_expandingincludes(&datafilename, &mut includestack)?;
+
+	let expandedblocks = expandtemplates(splitonblanklines(filelines))?;
 
-	let mut filelines: Vec<String> = fs::read_to_string(datafilename)
-	    .expect("should be able to open data file")
-		.lines()
-		.map(|line| line.trim())
-		.filter(|&line| !COMMENTLINEREGEX.is_match(line))
-		.map(|line| line.to_owned())
-		.collect();
-	
 	let allproducts: HashSet<&str> = db.products
 	    .iter().map(Product::name).collect();
 	let mut productresolver = AbbreviationResolver::new(
 		&allproducts
     );
-	
+
 	let allcategories: HashSet<&str> = db.facilitycategories
 	    .iter().map(FacilityCategory::name).collect();
 	let mut categoryresolver = AbbreviationResolver::new(
 		&allcategories
 	);
-	
-	for (i, line) in filelines.iter_mut().enumerate() {
-		
-		let m = INGREDIENTLINEREGEX.captures(line);
-		if let Some(m) = m {
-			let (_, [numberstring, productname]) = m.extract();
-			if !allproducts.contains(productname) {
-				let fullproductname = productresolver.call(productname);
-				*line = format!("{} {}", numberstring, fullproductname);
-			}
-			continue;
-		}
 
-		let m = ARROWLINEREGEX.captures(line);
-		if let Some(m) = m {
-			let (_, [secondsstring, categoryname]) = m.extract();
-			if !allcategories.contains(categoryname) {
-				let fullcategoryname = categoryresolver.call(categoryname);
-				*line = format!("^ {} s ({})", secondsstring, fullcategoryname);
-			}
-			continue;
-		}
+	let mut tokenblocks = Vec::with_capacity(expandedblocks.len());
+	for block in &expandedblocks {
+		let mut tokens = lex(block)?;
+		resolveabbreviations(&mut tokens, &mut productresolver, &mut categoryresolver, &allproducts, &allcategories)?;
+		tokenblocks.push(tokens);
 	}
-	
-	let recipeblocks = splitonblanklines(&filelines);
-	recipeblocks.into_iter().map(Vec::from).collect()
+
+	Ok(tokenblocks)
 }
 
 
@@ -130,81 +680,191 @@ pub fn readrecipefile(datafilename: PathBuf, db: &DataBase) -> Vec<Vec<String>>
 // Functions for Parsing a Single Recipe //
 ///////////////////////////////////////////
 
-fn parseingredient(recipeblock: &Vec<String>, i: usize) -> Option<ProductQuantity<i64>> {
-	let line = match recipeblock.get(i) {
-		Some(line) => line,
-		None => return None,
+// Returns Ok(None) when the cursor isn't sitting on a Number (so the caller
+// knows to stop collecting outputs/inputs), and only reaches an Err once a
+// Number has already been consumed but no product-name Ident follows it
+fn parseingredient(cursor: &mut TokenCursor) -> Result<Option<ProductQuantity<Rational64>>, RecipeParseError> {
+	let quantity = match cursor.peek() {
+		Some(Token { kind: TokenKind::Number(quantity), .. }) => *quantity,
+		_ => return Ok(None),
 	};
+	let startline = cursor.currentline();
+	cursor.advance();
 
-	match INGREDIENTLINEREGEX.captures(line) {
-		Some(m) => {
-			let (_, [numberstring, productname]) = m.extract();
-			let quantity = numberstring.parse().unwrap();
-			Some(ProductQuantity::with_productname(quantity, productname))
-		},
-		None => None,
-	}
+	let (namekind, _) = expecttoken(cursor, startline, |k| matches!(k, TokenKind::Ident(_)),
+		|line, _| RecipeParseError::ExpectedProductName { line })?;
+	let productname = match namekind { TokenKind::Ident(name) => name, _ => unreachable!() };
+
+	Ok(Some(ProductQuantity::with_productname(quantity, &productname)))
 }
 
-fn parsearrowline(recipeblock: &Vec<String>, i: usize) -> (Time, FacilityCategory) {
-	let line = match recipeblock.get(i) {
-		Some(line) => line,
-		None => panic!("arrow line index out of bounds"),
-	};
+fn parsearrowline(cursor: &mut TokenCursor) -> Result<(Time, FacilityCategory), RecipeParseError> {
+	let startline = cursor.currentline();
 
-	match ARROWLINEREGEX.captures(line) {
-		Some(m) => {
-			let (_, [secondsstring, categoryname]) = m.extract();
-			let t = Time::new(readrational(secondsstring));
-			let fc = FacilityCategory::new(categoryname);
-			(t, fc)
-		},
-		None => panic!("unable to parse arrow line: {:?}", line),
+	if cursor.peek().is_none() {
+		return Err(RecipeParseError::MissingArrowLine { line: startline });
 	}
+
+	expecttoken(cursor, startline, |k| matches!(k, TokenKind::Arrow),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+
+	let (secondskind, _) = expecttoken(cursor, startline, |k| matches!(k, TokenKind::Number(_)),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+	let seconds = match secondskind { TokenKind::Number(q) => q, _ => unreachable!() };
+
+	expecttoken(cursor, startline, |k| matches!(k, TokenKind::Seconds),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+	expecttoken(cursor, startline, |k| matches!(k, TokenKind::LParen),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+
+	let (categorykind, _) = expecttoken(cursor, startline, |k| matches!(k, TokenKind::Ident(_)),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+	let categoryname = match categorykind { TokenKind::Ident(name) => name, _ => unreachable!() };
+
+	expecttoken(cursor, startline, |k| matches!(k, TokenKind::RParen),
+		|line, text| RecipeParseError::UnparsableArrowLine { line, text })?;
+
+	Ok((Time::new(seconds), FacilityCategory::new(&categoryname)))
 }
 
-fn parsenametagline(recipeblock: &Vec<String>, i: usize) -> Option<&str> {
-	let line = match recipeblock.get(i) {
-		Some(line) => line,
-		None => return None,
+// An absent nametag (cursor already exhausted) is fine -- Ok(None). A
+// NameTag Ident pair that isn't the very last thing in the block is a
+// NametagBeforeEnd error, since nothing may follow a recipe's name
+fn parsenametagline(cursor: &mut TokenCursor) -> Result<Option<String>, RecipeParseError> {
+	let first = match cursor.peek() {
+		Some(token) => token.clone(),
+		None => return Ok(None),
 	};
 
-	if i + 1 != recipeblock.len() {
-		panic!("started to parse nametag line before end of recipe");
+	let remaininglines: HashSet<usize> = cursor.tokens[cursor.pos..].iter()
+	    .take_while(|t| !matches!(t.kind, TokenKind::Eof))
+		.map(|t| t.line)
+		.collect();
+	if remaininglines.len() > 1 {
+		return Err(RecipeParseError::NametagBeforeEnd {
+			line: first.line,
+			text: format!("{:?}", first.kind),
+		});
 	}
 
-	match NAMETAGLINEREGEX.captures(line) {
-		Some(m) => {
-			let (_, [recipename]) = m.extract();
-			Some(recipename)
-		},
-		None => panic!("unable to parse nametag line: {:?}", line),
+	expecttoken(cursor, first.line, |k| matches!(k, TokenKind::NameTag),
+		|line, text| RecipeParseError::UnparsableNametag { line, text })?;
+	let (namekind, _) = expecttoken(cursor, first.line, |k| matches!(k, TokenKind::Ident(_)),
+		|line, text| RecipeParseError::UnparsableNametag { line, text })?;
+	let name = match namekind { TokenKind::Ident(n) => n, _ => unreachable!() };
+
+	if let Some(extra) = cursor.peek() {
+		return Err(RecipeParseError::NametagBeforeEnd {
+			line: extra.line,
+			text: format!("{:?}", extra.kind),
+		});
 	}
+
+	Ok(Some(name))
 }
 
-pub fn readrecipe(recipeblock: &Vec<String>) -> Recipe {
-	let mut i = 0;
-	
+pub fn readrecipe(recipeblock: &[Token]) -> Result<Recipe, RecipeParseError> {
+	if recipeblock.iter().all(|t| matches!(t.kind, TokenKind::Eof | TokenKind::Comment)) {
+		let line = recipeblock.last().map_or(0, |t| t.line);
+		return Err(RecipeParseError::EmptyRecipeBlock { line });
+	}
+
+	let mut cursor = TokenCursor::new(recipeblock);
+
 	let mut r_outputs = Vec::new();
-	let mut ingredient = parseingredient(recipeblock, i);
-	while let Some(quantity) = ingredient {
+	while let Some(quantity) = parseingredient(&mut cursor)? {
 		r_outputs.push(quantity);
-		i += 1;
-		ingredient = parseingredient(recipeblock, i);
 	}
-	
-	let (r_period, r_madein) = parsearrowline(recipeblock, i);
-	i += 1;
-	
+
+	let (r_period, r_madein) = parsearrowline(&mut cursor)?;
+
 	let mut r_inputs = Vec::new();
-	let mut ingredient = parseingredient(recipeblock, i);
-	while let Some(quantity) = ingredient {
+	while let Some(quantity) = parseingredient(&mut cursor)? {
 		r_inputs.push(quantity);
-		i += 1;
-		ingredient = parseingredient(recipeblock, i);
 	}
-	
-	let r_name = parsenametagline(recipeblock, i);
-	
-	Recipe::new(r_name.as_deref(), r_outputs, r_inputs, r_period, r_madein)
+
+	let r_name = parsenametagline(&mut cursor)?;
+
+	Ok(Recipe::new(r_name.as_deref(), r_outputs, r_inputs, r_period, r_madein))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lines(raw: &[&str]) -> Vec<(usize, String)> {
+		raw.iter().enumerate().map(|(i, &line)| (i + 1, line.to_owned())).collect()
+	}
+
+	#[test]
+	fn lexes_and_parses_a_simple_recipe() {
+		let block = lines(&["10 gear", "^ 2s (assembler)", "5 iron"]);
+		let tokens = lex(&block).unwrap();
+
+		let recipe = readrecipe(&tokens).unwrap();
+
+		assert_eq!(recipe.outputs().len(), 1);
+		assert_eq!(recipe.outputs()[0].quantity(), Rational64::new(10, 1));
+		assert_eq!(recipe.outputs()[0].name(), "gear");
+		assert_eq!(recipe.inputs().len(), 1);
+		assert_eq!(recipe.inputs()[0].quantity(), Rational64::new(5, 1));
+		assert_eq!(recipe.period(), Time::new(Rational64::new(2, 1)));
+		assert_eq!(recipe.category().name(), "assembler");
+	}
+
+	#[test]
+	fn lexes_decimal_and_fraction_quantities() {
+		let block = lines(&["0.5 heavy_oil", "2/3 petroleum", "^ 1s (refinery)", "1 crude_oil", "<> refining"]);
+		let tokens = lex(&block).unwrap();
+
+		assert_eq!(tokens[0].kind, TokenKind::Number(Rational64::new(1, 2)));
+		let recipe = readrecipe(&tokens).unwrap();
+		assert_eq!(recipe.outputs()[1].quantity(), Rational64::new(2, 3));
+	}
+
+	#[test]
+	fn strips_whole_line_and_inline_comments() {
+		let block = lines(&["# a whole-line comment", "10 gear # trailing note"]);
+		let tokens = lex(&block).unwrap();
+
+		let kinds: Vec<&TokenKind> = tokens.iter().map(|t| &t.kind).collect();
+		assert_eq!(
+			kinds,
+			vec![&TokenKind::Comment, &TokenKind::Number(Rational64::new(10, 1)), &TokenKind::Ident("gear".to_owned()), &TokenKind::Eof]
+		);
+	}
+
+	#[test]
+	fn rejects_a_zero_denominator_quantity() {
+		let block = lines(&["1/0 gear"]);
+		let err = lex(&block).unwrap_err();
+		assert!(matches!(err, RecipeParseError::UnparsableQuantity { line: 1, .. }));
+	}
+
+	#[test]
+	fn rejects_a_quantity_with_no_product_name() {
+		let block = lines(&["10", "^ 2s (assembler)"]);
+		let tokens = lex(&block).unwrap();
+
+		let err = readrecipe(&tokens).unwrap_err();
+		assert!(matches!(err, RecipeParseError::ExpectedProductName { .. }));
+	}
+
+	#[test]
+	fn rejects_a_block_with_no_arrow_line() {
+		let block = lines(&["10 gear"]);
+		let tokens = lex(&block).unwrap();
+
+		let err = readrecipe(&tokens).unwrap_err();
+		assert!(matches!(err, RecipeParseError::MissingArrowLine { .. }));
+	}
+
+	#[test]
+	fn rejects_an_empty_block() {
+		let block: Vec<(usize, String)> = Vec::new();
+		let tokens = lex(&block).unwrap();
+
+		let err = readrecipe(&tokens).unwrap_err();
+		assert!(matches!(err, RecipeParseError::EmptyRecipeBlock { .. }));
+	}
 }
@@ -3,6 +3,8 @@
 
 use std::fmt;
 
+use num::rational::Rational64;
+
 use crate::facilities::FacilityCategory;
 use crate::products::ProductQuantity;
 use crate::rates::Time;
@@ -11,8 +13,8 @@ use crate::rates::Time;
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Recipe {
 	name: String,
-	outputs: Vec<ProductQuantity<i64>>,
-	inputs: Vec<ProductQuantity<i64>>,
+	outputs: Vec<ProductQuantity<Rational64>>,
+	inputs: Vec<ProductQuantity<Rational64>>,
 	period: Time,
 	madein: FacilityCategory,
 }
@@ -20,8 +22,8 @@ pub struct Recipe {
 impl Recipe {
 	pub fn with_name(
 		name: &str,
-		outputs: Vec<ProductQuantity<i64>>,
-		inputs: Vec<ProductQuantity<i64>>,
+		outputs: Vec<ProductQuantity<Rational64>>,
+		inputs: Vec<ProductQuantity<Rational64>>,
 		period: Time,
 		madein: FacilityCategory,
 	) -> Self {
@@ -54,8 +56,8 @@ impl Recipe {
 	}
 
 	pub fn without_name(
-		outputs: Vec<ProductQuantity<i64>>,
-		inputs: Vec<ProductQuantity<i64>>,
+		outputs: Vec<ProductQuantity<Rational64>>,
+		inputs: Vec<ProductQuantity<Rational64>>,
 		period: Time,
 		madein: FacilityCategory,
 	) -> Self {
@@ -70,8 +72,8 @@ impl Recipe {
 
 	pub fn new(
 		name: Option<&str>,
-		outputs: Vec<ProductQuantity<i64>>,
-		inputs: Vec<ProductQuantity<i64>>,
+		outputs: Vec<ProductQuantity<Rational64>>,
+		inputs: Vec<ProductQuantity<Rational64>>,
 		period: Time,
 		madein: FacilityCategory,
 	) -> Self {
@@ -85,11 +87,11 @@ impl Recipe {
 		&self.name
 	}
 
-	pub fn outputs(&self) -> &Vec<ProductQuantity<i64>> {
+	pub fn outputs(&self) -> &Vec<ProductQuantity<Rational64>> {
 		&self.outputs
 	}
 
-	pub fn inputs(&self) -> &Vec<ProductQuantity<i64>> {
+	pub fn inputs(&self) -> &Vec<ProductQuantity<Rational64>> {
 		&self.inputs
 	}
 
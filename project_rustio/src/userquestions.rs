@@ -1,9 +1,10 @@
 // userquestions.rs
 // Functions for acquiring a few types of terminal input from the user
 
+use std::cell::RefCell;
 use std::fmt;
-use std::io;
 
+use rustyline::DefaultEditor;
 use terminal_menu;
 
 
@@ -90,23 +91,66 @@ pub fn getuserbool(question: &str, default: bool) -> bool {
 // Ask User to Enter a Line of Text for Parsing //
 //////////////////////////////////////////////////
 
+// One rustyline editor per thread lives as long as that thread, so history from earlier
+// getusertext() calls (e.g. previously accepted recipe entries) is still
+// available for up-arrow recall later in the same session
+thread_local! {
+	static EDITOR: RefCell<DefaultEditor> = RefCell::new(
+		DefaultEditor::new().expect("should be able to create a line editor")
+	);
+}
+
+// A line ending in this marker means the user isn't done yet -- keep
+// reading lines (dropping the marker and joining with newlines) until one
+// doesn't end with it, so multi-line recipe data can be entered in one go
+const CONTINUATIONMARKER: &str = "\\";
+const CONTINUATIONPROMPT: &str = "... ";
+
+fn readusertextline(prompt: &str) -> String {
+	EDITOR.with(|editor| {
+		let mut editor = editor.borrow_mut();
+		let line = editor.readline(prompt).unwrap_or_default();
+		let _ = editor.add_history_entry(line.as_str());
+		line
+	})
+}
+
 // Ask the user to enter a line of text until it passes some given parse test
 // Prompt with a message, then pass the input string to the parser function
-// Repeat until the parser returns not nothing, then return whatever it gave
-// By default, just return whatever the first string is that the user enters
-pub fn getusertext<T>(message: &str, parser: impl Fn(&str) -> Option<T>) -> T {
-	let mut parsevalue;
-	
+// Repeat until the parser returns Ok, then return whatever it gave. A parse
+// failure prints the parser's own error message before reprompting, instead
+// of silently asking again with no explanation
+pub fn getusertext<T, E: fmt::Display>(message: &str, parser: impl Fn(&str) -> Result<T, E>) -> T {
 	loop {
 		println!("");
-		print!("{}", message);
-		let mut line = String::new();
-		io::stdin().read_line(&mut line).expect("should be able to read line");
-		parsevalue = parser(&line);
-		if parsevalue.is_some() {
-			break;
+
+		let mut buffer = String::new();
+		let mut prompt = message.to_owned();
+
+		loop {
+			let line = readusertextline(&prompt);
+
+			match line.strip_suffix(CONTINUATIONMARKER) {
+				Some(head) => {
+					if !buffer.is_empty() {
+						buffer.push('\n');
+					}
+					buffer.push_str(head);
+					prompt = CONTINUATIONPROMPT.to_owned();
+				},
+				None => {
+					if !buffer.is_empty() {
+						buffer.push('\n');
+					}
+					buffer.push_str(&line);
+					break;
+				},
+			}
+		}
+
+		match parser(&buffer) {
+			Ok(parsevalue) => return parsevalue,
+			Err(e) => println!("{}", e),
 		}
 	}
-	
-	parsevalue.unwrap()
 }